@@ -1,22 +1,177 @@
-import { makeWASocket, useMultiFileAuthState, fetchLatestBaileysVersion, makeCacheableSignalKeyStore, DisconnectReason } from '@whiskeysockets/baileys';
+import { makeWASocket, useMultiFileAuthState, fetchLatestBaileysVersion, makeCacheableSignalKeyStore, DisconnectReason, downloadMediaMessage } from '@whiskeysockets/baileys';
 import { Boom } from '@hapi/boom';
 import { createInterface } from 'readline';
+import { mkdirSync, writeFileSync } from 'fs';
+import { extname, join, resolve } from 'path';
 import pino from 'pino';
 import qrcode from 'qrcode-terminal';
 
+// Protocol between this bridge and kova-whatsapp, documented in PROTOCOL.md.
+const PROTOCOL = 1;
 const AUTH_DIR = process.env.BAILEYS_AUTH_DIR || './auth_state';
+const MEDIA_DIR = resolve(process.env.KOVA_MEDIA_DIR || './media');
+const MEDIA_MAX_BYTES = Number(process.env.KOVA_MEDIA_MAX_BYTES) || 20 * 1024 * 1024;
+// Chats whose media is downloaded; kova ignores everyone else's messages anyway.
+const MEDIA_FROM = new Set(JSON.parse(process.env.KOVA_MEDIA_FROM || '[]'));
+const EXTENSIONS = {
+    'image/jpeg': '.jpg', 'image/png': '.png', 'image/webp': '.webp', 'image/gif': '.gif',
+    'audio/ogg': '.ogg', 'audio/mpeg': '.mp3', 'audio/mp4': '.m4a', 'application/pdf': '.pdf',
+};
+const logger = pino({ level: 'silent' });
 const rl = createInterface({ input: process.stdin });
+let sock = null;
+// Whether the socket is open. Commands are refused with `retry` while it is not.
+let connected = false;
 
 function send(event) {
     process.stdout.write(JSON.stringify(event) + '\n');
 }
 
+// The WhatsApp key of the message a command refers to.
+function keyOf(jid, { messageId, participant, fromMe }) {
+    return { remoteJid: jid, id: messageId, participant, fromMe };
+}
+
+async function handle(cmd) {
+    if (!sock || !connected) throw new Error('not connected');
+    switch (cmd.type) {
+        case 'send': {
+            // Baileys builds the quote from a message object; its text is enough.
+            const quoted = cmd.quoted && { key: keyOf(cmd.jid, cmd.quoted), message: { conversation: cmd.quoted.text } };
+            const sent = await sock.sendMessage(cmd.jid, { text: cmd.text }, { quoted });
+            return { messageId: sent?.key?.id };
+        }
+        case 'send_file': {
+            const source = { url: cmd.path };
+            const content = cmd.kind === 'image' ? { image: source, caption: cmd.caption }
+                : cmd.kind === 'audio' ? { audio: source, mimetype: cmd.mimeType }
+                : { document: source, mimetype: cmd.mimeType, fileName: cmd.fileName, caption: cmd.caption };
+            const sent = await sock.sendMessage(cmd.jid, content);
+            return { messageId: sent?.key?.id };
+        }
+        case 'presence':
+            await sock.sendPresenceUpdate(cmd.state, cmd.jid);
+            return {};
+        case 'read':
+            await sock.readMessages([keyOf(cmd.jid, cmd)]);
+            return {};
+        case 'react': {
+            const sent = await sock.sendMessage(cmd.jid, { react: { text: cmd.emoji, key: keyOf(cmd.jid, cmd) } });
+            return { messageId: sent?.key?.id };
+        }
+        default:
+            throw new Error(`unknown command: ${cmd.type}`);
+    }
+}
+
+// Commands run one at a time, so the parts of a long reply arrive in order.
+// Each is answered with an `ack` or `error` carrying its id.
+let queue = Promise.resolve();
+rl.on('line', (line) => {
+    queue = queue.then(async () => {
+        let cmd;
+        try {
+            cmd = JSON.parse(line);
+        } catch (e) {
+            send({ type: 'error', message: `bad command: ${e.message}` });
+            return;
+        }
+        try {
+            const result = await handle(cmd);
+            send({ type: 'ack', id: cmd.id, ...result });
+        } catch (e) {
+            // kova-whatsapp sends commands refused or cut off by a lost connection again.
+            send({ type: 'error', id: cmd.id, message: e.message, retry: !connected || undefined });
+        }
+    });
+});
+
+// The media part of a message, for the kinds kova handles.
+function mediaOf(message) {
+    const document = message?.documentMessage || message?.documentWithCaptionMessage?.message?.documentMessage;
+    if (message?.imageMessage) return { kind: 'image', media: message.imageMessage };
+    if (document) return { kind: 'document', media: document };
+    if (message?.audioMessage) return { kind: 'audio', media: message.audioMessage };
+    return null;
+}
+
+// The `attachment` fields of a message's media, before it is downloaded.
+function attachmentOf({ kind, media }) {
+    return {
+        kind,
+        mimeType: (media.mimetype || '').split(';')[0].trim(),
+        fileName: media.fileName || undefined,
+        size: Number(String(media.fileLength ?? '')) || undefined,
+        seconds: media.seconds || undefined,
+    };
+}
+
+// Downloads a message's media into MEDIA_DIR. Failures are reported in `error`.
+async function saveAttachment(msg, found) {
+    const { media } = found;
+    const attachment = attachmentOf(found);
+    const mimeType = attachment.mimeType;
+    if (!MEDIA_FROM.has(msg.key.remoteJid)) {
+        return { ...attachment, error: 'not downloaded in chats outside whatsapp.senders' };
+    }
+    if (attachment.size > MEDIA_MAX_BYTES) {
+        return { ...attachment, error: `larger than the ${MEDIA_MAX_BYTES} byte limit` };
+    }
+    try {
+        const buffer = await downloadMediaMessage(msg, 'buffer', {}, { logger, reuploadRequest: sock.updateMediaMessage });
+        if (buffer.length > MEDIA_MAX_BYTES) {
+            return { ...attachment, error: `larger than the ${MEDIA_MAX_BYTES} byte limit` };
+        }
+        const ext = extname(media.fileName || '') || EXTENSIONS[mimeType] || '.bin';
+        const path = join(MEDIA_DIR, msg.key.id.replace(/[^A-Za-z0-9]/g, '_') + ext);
+        mkdirSync(MEDIA_DIR, { recursive: true });
+        writeFileSync(path, buffer);
+        return { ...attachment, path, size: buffer.length };
+    } catch (e) {
+        return { ...attachment, error: e.message };
+    }
+}
+
+function textOf(message) {
+    return message?.conversation
+        || message?.extendedTextMessage?.text
+        || mediaOf(message)?.media.caption
+        || '';
+}
+
+async function forward(msg) {
+    const found = mediaOf(msg.message);
+    const text = textOf(msg.message);
+    if (!text && !found) return;
+    // Mentions and the quoted message live in the context info of the message part.
+    const context = msg.message?.extendedTextMessage?.contextInfo || found?.media.contextInfo;
+
+    send({
+        type: 'message',
+        jid: msg.key.remoteJid,
+        participant: msg.key.participant || undefined,
+        text,
+        pushName: msg.pushName || '',
+        messageId: msg.key.id,
+        fromMe: msg.key.fromMe || false,
+        mentions: context?.mentionedJid?.length ? context.mentionedJid : undefined,
+        quoted: context?.stanzaId ? {
+            messageId: context.stanzaId,
+            participant: context.participant || undefined,
+            text: textOf(context.quotedMessage),
+        } : undefined,
+        attachment: found ? await saveAttachment(msg, found) : undefined,
+    });
+}
+
+// Messages are forwarded in arrival order, even while earlier media downloads.
+let inbox = Promise.resolve();
+
 async function start() {
-    const logger = pino({ level: 'silent' });
     const { state, saveCreds } = await useMultiFileAuthState(AUTH_DIR);
     const { version } = await fetchLatestBaileysVersion();
 
-    const sock = makeWASocket({
+    sock = makeWASocket({
         auth: {
             creds: state.creds,
             keys: makeCacheableSignalKeyStore(state.keys, logger),
@@ -38,6 +193,7 @@ async function start() {
             send({ type: 'qr', data: qr });
         }
         if (connection === 'close') {
+            connected = false;
             const reason = new Boom(lastDisconnect?.error)?.output?.statusCode;
             if (reason === DisconnectReason.loggedOut) {
                 send({ type: 'disconnected', reason: 'logged_out' });
@@ -48,7 +204,8 @@ async function start() {
             return; // Don't process further
         }
         if (connection === 'open') {
-            send({ type: 'connected' });
+            connected = true;
+            send({ type: 'connected', jid: sock.user?.id, lid: sock.user?.lid });
         }
     });
 
@@ -62,35 +219,14 @@ async function start() {
         // Only process real-time notifications, not history sync
         if (type !== 'notify') return;
         for (const msg of messages) {
-            const text = msg.message?.conversation
-                || msg.message?.extendedTextMessage?.text
-                || '';
-            if (!text) continue;
-
-            send({
-                type: 'message',
-                jid: msg.key.remoteJid,
-                text,
-                pushName: msg.pushName || '',
-                messageId: msg.key.id,
-                fromMe: msg.key.fromMe || false,
+            inbox = inbox.then(() => forward(msg)).catch((e) => {
+                send({ type: 'error', message: `dropped message ${msg.key.id}: ${e.message}` });
             });
         }
     });
-
-    rl.on('line', async (line) => {
-        try {
-            const cmd = JSON.parse(line);
-            if (cmd.type === 'send') {
-                await sock.sendMessage(cmd.jid, { text: cmd.text });
-                send({ type: 'sent', jid: cmd.jid, messageId: cmd.messageId || '' });
-            }
-        } catch (e) {
-            send({ type: 'error', message: e.message });
-        }
-    });
 }
 
+send({ type: 'hello', protocol: PROTOCOL, bridge: 'baileys' });
 start().catch((e) => {
     send({ type: 'error', message: e.message });
     process.exit(1);
@@ -0,0 +1,92 @@
+// Stand-in for baileys_bridge.js that needs no WhatsApp account.
+//
+// Says hello, replays the bridge events in FAKE_BRIDGE_EVENTS (one JSON object per
+// line; an optional `delayMs` waits before the event), and answers every `send`
+// and `send_file` command the way WhatsApp does: an `ack` with a fresh message id,
+// followed by the `fromMe` echo of the same message. `presence`, `read` and `react`
+// are just acked. Speaks the protocol in PROTOCOL.md.
+// It is logged in as FAKE_BRIDGE_JID and connects FAKE_BRIDGE_CONNECT_MS (default
+// 100) after its hello; until then commands fail with `retry`, like Baileys' do.
+// FAKE_BRIDGE_SEND_MS makes every send take that long. FAKE_BRIDGE_ECHO_FIRST=1
+// delivers the echo before the ack, which WhatsApp sometimes does. SIGUSR1 drops
+// the connection and reconnects after FAKE_BRIDGE_CONNECT_MS; SIGUSR2 makes it
+// crash. These exercise the supervisor (see the tests in supervisor.rs).
+//
+//   KOVA_BRIDGE_SCRIPT=fake_bridge.js FAKE_BRIDGE_EVENTS=events.jsonl cargo run -p kova-whatsapp
+import { createInterface } from 'readline';
+import { readFileSync } from 'fs';
+
+const PROTOCOL = 1;
+const JID = process.env.FAKE_BRIDGE_JID || '905550000000:7@s.whatsapp.net';
+const CONNECT_MS = Number(process.env.FAKE_BRIDGE_CONNECT_MS ?? 100);
+const SEND_MS = Number(process.env.FAKE_BRIDGE_SEND_MS ?? 0);
+const ECHO_FIRST = process.env.FAKE_BRIDGE_ECHO_FIRST === '1';
+const rl = createInterface({ input: process.stdin });
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+let sent = 0;
+let connected = false;
+
+function send(event) {
+    process.stdout.write(JSON.stringify(event) + '\n');
+}
+
+async function connect() {
+    await sleep(CONNECT_MS);
+    connected = true;
+    send({ type: 'connected', jid: JID });
+}
+
+async function replay(path) {
+    send({ type: 'hello', protocol: PROTOCOL, bridge: 'fake' });
+    await connect();
+    if (!path) return;
+    const lines = readFileSync(path, 'utf8').split('\n').filter((l) => l.trim());
+    for (const line of lines) {
+        const { delayMs, ...event } = JSON.parse(line);
+        await sleep(delayMs ?? 100);
+        send(event);
+    }
+}
+
+// Like the real bridge, commands run one at a time.
+let queue = Promise.resolve();
+rl.on('line', (line) => {
+    queue = queue.then(() => handle(JSON.parse(line)));
+});
+
+async function handle(cmd) {
+    process.stderr.write(`[fake-bridge] ${JSON.stringify(cmd)}\n`);
+    if (!connected) {
+        send({ type: 'error', id: cmd.id, message: 'not connected', retry: true });
+    } else if (cmd.type === 'send' || cmd.type === 'send_file') {
+        await sleep(SEND_MS);
+        // Predictable ids, so scripted events can quote kova's messages: 3EB00000000000000001, ...
+        const messageId = '3EB0' + String(++sent).padStart(16, '0');
+        const attachment = cmd.path && { kind: cmd.kind, mimeType: cmd.mimeType, fileName: cmd.fileName, path: cmd.path };
+        const text = cmd.text ?? cmd.caption ?? '';
+        const quoted = cmd.quoted && { messageId: cmd.quoted.messageId, participant: cmd.quoted.participant, text: cmd.quoted.text };
+        const ack = { type: 'ack', id: cmd.id, messageId };
+        const echo = { type: 'message', jid: cmd.jid, text, pushName: '', messageId, fromMe: true, quoted, attachment };
+        const [first, second] = ECHO_FIRST ? [echo, ack] : [ack, echo];
+        send(first);
+        await sleep(50);
+        send(second);
+    } else if (['presence', 'read', 'react'].includes(cmd.type)) {
+        send({ type: 'ack', id: cmd.id });
+    } else {
+        send({ type: 'error', id: cmd.id, message: `unknown command: ${cmd.type}` });
+    }
+}
+
+process.on('SIGUSR1', () => {
+    process.stderr.write('[fake-bridge] connection lost on SIGUSR1\n');
+    connected = false;
+    connect();
+});
+
+process.on('SIGUSR2', () => {
+    process.stderr.write('[fake-bridge] crashing on SIGUSR2\n');
+    process.exit(1);
+});
+
+replay(process.env.FAKE_BRIDGE_EVENTS);
@@ -0,0 +1,150 @@
+//! Slash commands of the REPL: parsing, help and tab completion.
+//!
+//! Input starting with `/` is a command; anything else goes to the agent.
+
+use kova_core::session::Session;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::path::PathBuf;
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec { name: "/help", usage: "[command]", help: "List commands, or explain one" },
+    CommandSpec { name: "/reset", usage: "", help: "Forget the conversation (the session file keeps a record)" },
+    CommandSpec { name: "/model", usage: "[name]", help: "Show the model, or switch to another one of the same backend" },
+    CommandSpec { name: "/tools", usage: "", help: "List the tools with what the cli policy does with their calls" },
+    CommandSpec { name: "/cd", usage: "[dir]", help: "Show or change the directory tools resolve relative paths against" },
+    CommandSpec { name: "/session", usage: "list | load <id> | new", help: "List sessions, continue one, or start a new one" },
+    CommandSpec { name: "/save", usage: "<file>", help: "Write the conversation to a Markdown file" },
+    CommandSpec { name: "/undo", usage: "", help: "Drop your last message and everything the agent did for it" },
+    CommandSpec { name: "/exit", usage: "", help: "Quit (so do `exit`, `quit`, Ctrl+C and Ctrl+D)" },
+];
+
+const SESSION_ACTIONS: &[&str] = &["list", "load", "new"];
+
+pub enum Command {
+    Help(Option<String>),
+    Reset,
+    Model(Option<String>),
+    Tools,
+    Cd(Option<String>),
+    SessionList,
+    SessionLoad(String),
+    SessionNew,
+    Save(PathBuf),
+    Undo,
+    Exit,
+}
+
+/// Parses a line starting with `/`. `Err` holds what to tell the user.
+pub fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+    let command = match (name, args.as_slice()) {
+        ("/help", []) => Command::Help(None),
+        ("/help", [topic]) => Command::Help(Some(topic.to_string())),
+        ("/reset", []) => Command::Reset,
+        ("/model", []) => Command::Model(None),
+        ("/model", [model]) => Command::Model(Some(model.to_string())),
+        ("/tools", []) => Command::Tools,
+        ("/cd", []) => Command::Cd(None),
+        ("/cd", [dir]) => Command::Cd(Some(dir.to_string())),
+        ("/session", ["list"]) => Command::SessionList,
+        ("/session", ["load", id]) => Command::SessionLoad(id.to_string()),
+        ("/session", ["new"]) => Command::SessionNew,
+        ("/save", [file]) => Command::Save(PathBuf::from(file)),
+        ("/undo", []) => Command::Undo,
+        ("/exit" | "/quit", []) => Command::Exit,
+        _ => {
+            return Err(match spec(name) {
+                Some(spec) => format!("usage: {} {}", spec.name, spec.usage),
+                None => format!("unknown command {name}, see /help"),
+            })
+        }
+    };
+    Ok(command)
+}
+
+fn spec(name: &str) -> Option<&'static CommandSpec> {
+    let name = if name.starts_with('/') { name.to_string() } else { format!("/{name}") };
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// The command list, or the help of `topic`.
+pub fn help(topic: Option<&str>) -> String {
+    match topic {
+        Some(topic) => match spec(topic) {
+            Some(spec) => format!("{} {}\n  {}", spec.name, spec.usage, spec.help),
+            None => format!("unknown command {topic}, see /help"),
+        },
+        None => COMMANDS.iter()
+            .map(|c| format!("  {:<32} {}", format!("{} {}", c.name, c.usage), c.help))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Completes command names, `/session` actions, session ids and `/help` topics.
+pub struct CommandCompleter {
+    pub session_dir: PathBuf,
+}
+
+impl CommandCompleter {
+    /// Completions of the last word. Words that take arguments end in a space.
+    fn candidates(&self, words: &[&str]) -> Vec<String> {
+        match words {
+            [_] => COMMANDS.iter()
+                .map(|c| if c.usage.is_empty() { c.name.to_string() } else { format!("{} ", c.name) })
+                .collect(),
+            ["/help", _] => COMMANDS.iter().map(|c| c.name.trim_start_matches('/').to_string()).collect(),
+            ["/session", _] => SESSION_ACTIONS.iter()
+                .map(|a| if *a == "load" { format!("{a} ") } else { a.to_string() })
+                .collect(),
+            ["/session", "load", _] => Session::list(&self.session_dir)
+                .map(|sessions| sessions.into_iter().map(|s| s.id).collect())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        if !line.starts_with('/') {
+            return Ok((pos, Vec::new()));
+        }
+        // The word being typed starts after the last space; a trailing space starts a new one.
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let mut words: Vec<&str> = line[..start].split_whitespace().collect();
+        words.push(&line[start..]);
+        let typed = &line[start..];
+        let pairs = self.candidates(&words)
+            .into_iter()
+            .filter(|c| c.starts_with(typed))
+            .map(|c| Pair { display: c.trim_end().to_string(), replacement: c })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CommandCompleter {}
+
+impl Validator for CommandCompleter {}
+
+impl Helper for CommandCompleter {}
@@ -1,136 +1,493 @@
-use anyhow::Result;
+mod commands;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use clap::{Parser, Subcommand};
+use commands::{Command, CommandCompleter};
 use kova_core::agent::Agent;
-use kova_core::config::Config;
-use kova_core::llm::LlmClient;
+use kova_core::approval::{Approval, Approver, AutoApprove};
+use kova_core::config::{self, Config, LlmConfig, SenderRole};
+use kova_core::event::{EventPayload, Message, Role};
+use kova_core::llm;
 use kova_core::session::Session;
+use kova_core::text;
+use kova_core::tools::shell::ShellExec;
+use kova_core::tools::{ToolCall, ToolRegistry};
+use futures::StreamExt;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
 use std::io::{self, BufRead, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// KovaClaw agent: a REPL, or one-shot prompts.
+#[derive(Parser)]
+struct Args {
+    /// Config file instead of config/kovaclaw.json in the project root
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Model to use instead of the configured one
+    #[arg(long, global = true, value_name = "NAME")]
+    model: Option<String>,
+    /// System prompt file instead of the configured identity
+    #[arg(long, global = true, value_name = "FILE")]
+    identity: Option<PathBuf>,
+    /// Give the agent no tools
+    #[arg(long, global = true)]
+    no_tools: bool,
+    /// Approve every tool call the policy would ask about
+    #[arg(long, global = true)]
+    yes: bool,
+    #[command(flatten)]
+    session: SessionArgs,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(clap::Args, Default)]
+struct SessionArgs {
+    /// Continue the most recent session, or session ID with --resume=ID
+    #[arg(long, value_name = "ID", num_args = 0..=1, require_equals = true, default_missing_value = "", conflicts_with = "fork")]
+    resume: Option<String>,
+    /// Start a new session with a copy of session ID's history
+    #[arg(long, value_name = "ID")]
+    fork: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Chat with the agent (the default)
+    Chat(SessionArgs),
+    /// Answer one prompt and exit
+    Ask {
+        prompt: String,
+        #[command(flatten)]
+        session: SessionArgs,
+    },
+    /// Show the saved sessions
+    Sessions {
+        #[command(subcommand)]
+        action: Option<SessionsAction>,
+    },
+    /// List the tools with what the cli policy does with their calls
+    Tools,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionsAction {
+    /// List sessions with their first message, size and last activity (the default)
+    List,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Load the config, identity, workspace and policies and report what is wrong
+    Check,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
+    let args = Args::parse();
 
-    let base_dir = find_project_root()?;
-    let config_path = base_dir.join("config/kovaclaw.json");
-    let config = Config::load(&config_path)?;
-    let identity = config.load_identity(&base_dir)?;
-
+    let (base_dir, config_path) = match &args.config {
+        Some(path) => (config::project_root_of(path), path.clone()),
+        None => {
+            let base_dir = config::find_project_root()?;
+            let config_path = base_dir.join(config::CONFIG_FILE);
+            (base_dir, config_path)
+        }
+    };
+    let mut config = Config::load(&config_path)
+        .with_context(|| format!("loading {}", config_path.display()))?;
+    if let Some(model) = &args.model {
+        config.llm.model = model.clone();
+    }
+    if let Some(identity) = &args.identity {
+        // Relative to where kova-cli runs, not to the project root.
+        config.identity_path = std::path::absolute(identity)?;
+    }
     let session_dir = base_dir.join(&config.session_dir);
-    let session_id = chrono::Utc::now().format("%Y%m%d_%H%M%S").to_string();
-    let session = Session::new(&session_dir, &session_id)?;
 
-    let llm = LlmClient::new(config.llm);
+    let approver: Box<dyn Approver> = if args.yes { Box::new(AutoApprove) } else { Box::new(CliApprover) };
+    // Without a subcommand kova-cli chats.
+    match args.command.unwrap_or(CliCommand::Chat(SessionArgs::default())) {
+        CliCommand::Sessions { action: None | Some(SessionsAction::List) } => print_sessions(&session_dir, None),
+        CliCommand::Tools => {
+            print_tools(&tool_registry(&config, &base_dir, args.no_tools)?);
+            Ok(())
+        }
+        CliCommand::Config { action: ConfigAction::Check } => check_config(&config, &config_path, &base_dir),
+        CliCommand::Ask { prompt, session } => {
+            let session = open_or_create(&session_dir, session.or(args.session))?;
+            let mut agent = build_agent(&config, &base_dir, args.no_tools, session)?;
+            chat(&mut agent, &prompt, approver.as_ref()).await
+        }
+        CliCommand::Chat(session) => {
+            let session = open_or_create(&session_dir, session.or(args.session))?;
+            let agent = build_agent(&config, &base_dir, args.no_tools, session)?;
+            repl(Cli { agent, llm: config.llm, session_dir, approver }).await
+        }
+    }
+}
+
+impl SessionArgs {
+    /// These args if any is set, else `fallback` (the ones given before the subcommand).
+    fn or(self, fallback: SessionArgs) -> SessionArgs {
+        if self.resume.is_some() || self.fork.is_some() { self } else { fallback }
+    }
+}
+
+/// The session `--resume` or `--fork` ask for, or a new one.
+fn open_or_create(session_dir: &Path, args: SessionArgs) -> Result<Session> {
+    Ok(match (args.resume, args.fork) {
+        (Some(id), _) if id.is_empty() => {
+            let latest = Session::list(session_dir)?.into_iter().next()
+                .ok_or_else(|| anyhow::anyhow!("no session to resume in {}", session_dir.display()))?;
+            Session::new(session_dir, &latest.id)?
+        }
+        (Some(id), _) => open_session(session_dir, &id)?,
+        (None, Some(id)) => {
+            let fork = open_session(session_dir, &id)?.fork(&new_session_id(session_dir))?;
+            println!("[forked session {id} into {}]", fork.id());
+            fork
+        }
+        (None, None) => Session::new(session_dir, &new_session_id(session_dir))?,
+    })
+}
+
+/// The tools of the cli channel; none with `--no-tools`.
+fn tool_registry(config: &Config, base_dir: &Path, no_tools: bool) -> Result<ToolRegistry> {
+    let mut tools = ToolRegistry::new();
+    if !no_tools {
+        tools.register_defaults();
+        tools.register(Box::new(ShellExec::new(config.shell.clone())));
+    }
+    tools.set_policy(config.policy.channel("cli")?);
+    tools.set_workspace(config.workspace(base_dir)?);
+    tools.set_concurrency(config.max_parallel_tools);
+    Ok(tools)
+}
+
+fn build_agent(config: &Config, base_dir: &Path, no_tools: bool, session: Session) -> Result<Agent> {
+    let identity = config.load_identity(base_dir)?;
+    let llm = llm::from_config(config.llm.clone())?;
     let mut agent = Agent::new(llm, identity).with_session(session)?;
+    agent.tools = tool_registry(config, base_dir, no_tools)?;
+    Ok(agent)
+}
 
-    println!("KovaClaw v0.2.0 (session: {session_id})");
-    println!("Tools: read_file, write_file, shell_exec");
-    println!("Ctrl+C or 'exit' to quit\n");
+async fn repl(mut cli: Cli) -> Result<()> {
+    let mut tool_names: Vec<String> = cli.agent.tools.definitions().into_iter().map(|d| d.name).collect();
+    tool_names.sort();
+    let session_id = cli.agent.session().map(|s| s.id()).unwrap_or_default();
+    println!("KovaClaw v0.2.0 (session: {session_id}, {} messages)", cli.agent.history().len());
+    println!("Tools: {}", if tool_names.is_empty() { "none".to_string() } else { tool_names.join(", ") });
+    println!("/help for commands, Ctrl+C or 'exit' to quit\n");
 
-    let stdin = io::stdin();
-    let mut stdout = io::stdout();
+    let mut editor = Editor::<CommandCompleter, DefaultHistory>::new()?;
+    editor.set_helper(Some(CommandCompleter { session_dir: cli.session_dir.clone() }));
 
     loop {
-        print!("kova> ");
-        stdout.flush()?;
-
-        let mut input = String::new();
-        if stdin.lock().read_line(&mut input)? == 0 {
-            break;
-        }
+        let input = match editor.readline("kova> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
 
         let input = input.trim();
         if input.is_empty() { continue; }
+        let _ = editor.add_history_entry(input);
         if input == "exit" || input == "quit" { break; }
 
-        // Stream response
-        print!("\n");
-        let mut writer = tokio::io::stdout();
-        let response = match agent.send_stream(input, &mut writer).await {
-            Ok(r) => r,
-            Err(e) => {
-                // Fallback to non-streaming
-                eprintln!("[stream failed, trying non-stream: {e}]");
-                match agent.send(input).await {
-                    Ok(r) => { print!("{r}"); r },
-                    Err(e2) => { eprintln!("[error] {e2}\n"); continue; }
+        if input.starts_with('/') {
+            match commands::parse(input) {
+                Ok(Command::Exit) => break,
+                Ok(command) => {
+                    if let Err(e) = cli.run_command(command) {
+                        eprintln!("[error] {e}");
+                    }
                 }
+                Err(message) => eprintln!("{message}"),
+            }
+            println!();
+            continue;
+        }
+
+        println!();
+        if let Err(e) = chat(&mut cli.agent, input, cli.approver.as_ref()).await {
+            eprintln!("\n[error] {e}\n");
+        }
+    }
+
+    Ok(())
+}
+
+/// `config check`: everything a frontend loads at startup, reported item by item.
+fn check_config(config: &Config, config_path: &Path, base_dir: &Path) -> Result<()> {
+    let mut problems = 0;
+    let mut report = |item: &str, result: Result<String>| match result {
+        Ok(detail) => println!("ok       {item}: {detail}"),
+        Err(e) => {
+            problems += 1;
+            println!("problem  {item}: {e:#}");
+        }
+    };
+
+    report("config", Ok(config_path.display().to_string()));
+    let identity_path = base_dir.join(&config.identity_path);
+    report("identity", config.load_identity(base_dir)
+        .with_context(|| identity_path.display().to_string())
+        .map(|identity| format!("{} ({} bytes)", identity_path.display(), identity.len())));
+    report("llm", llm::from_config(config.llm.clone())
+        .map(|_| format!("{:?} {} at {}", config.llm.provider, config.llm.model, config.llm.base_url)));
+    report("workspace", config.workspace(base_dir)
+        .map(|workspace| format!("{} root(s), cwd {}", workspace.roots().len(), workspace.cwd().display())));
+    let mut channels: Vec<&str> = config.policy.channels.keys().map(String::as_str).collect();
+    channels.extend(["cli", "whatsapp"].into_iter().filter(|c| !config.policy.channels.contains_key(*c)));
+    channels.sort();
+    for channel in channels {
+        let configured = config.policy.channels.contains_key(channel);
+        report(&format!("policy {channel}"), config.policy.channel(channel)
+            .map(|_| if configured { "compiles".to_string() } else { "not configured, defaults apply".to_string() }));
+    }
+    for role in [SenderRole::Owner, SenderRole::Trusted, SenderRole::Guest] {
+        let detail = match config.whatsapp.role(role).policy {
+            Some(channel) if !config.policy.channels.contains_key(&channel) => {
+                Err(anyhow::anyhow!("uses policy channel {channel}, which is not configured"))
             }
+            Some(channel) => Ok(format!("policy {channel}")),
+            None => Ok("no tools".to_string()),
         };
-        println!("\n");
-
-        // Check for tool calls in response
-        let tool_calls = Agent::parse_tool_calls(&response);
-        for call in tool_calls {
-            let tool = match agent.tools.get(&call.name) {
-                Some(t) => t,
-                None => {
-                    eprintln!("[unknown tool: {}]", call.name);
-                    continue;
-                }
-            };
+        report(&format!("whatsapp role {}", role.as_str()), detail);
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{problems} problem(s) in {}", config_path.display());
+    }
+    Ok(())
+}
+
+/// Sends one message to the agent and prints what it does until it answers. A failed
+/// turn is returned, so `ask` exits non-zero; the REPL reports it and goes on.
+async fn chat(agent: &mut Agent, input: &str, approver: &dyn Approver) -> Result<()> {
+    let mut stdout = io::stdout();
+    let stream = agent.run_stream(input, approver);
+    let mut stream = std::pin::pin!(stream);
 
-            // Approval flow
-            if tool.needs_approval() {
-                print!("[tool: {} | args: {}] approve? (y/n) ", call.name, call.arguments);
+    while let Some(event) = stream.next().await {
+        match event?.payload {
+            EventPayload::TextDelta { text } => {
+                print!("{text}");
                 stdout.flush()?;
-                let mut answer = String::new();
-                stdin.lock().read_line(&mut answer)?;
-                if answer.trim() != "y" {
-                    agent.feed_tool_result(&call.name, "Tool call denied by user.");
-                    println!("[denied]\n");
-                    continue;
-                }
-            } else {
-                println!("[tool: {} | auto-approved]", call.name);
             }
-
-            // Execute
-            match agent.tools.execute(&call) {
-                Ok(result) => {
-                    let preview = if result.output.len() > 200 {
-                        format!("{}...", &result.output[..200])
-                    } else {
-                        result.output.clone()
-                    };
-                    println!("[result: {}]\n{}\n", if result.success { "ok" } else { "fail" }, preview);
-                    agent.feed_tool_result(&call.name, &result.output);
-                }
-                Err(e) => {
-                    let err = format!("Execution error: {e}");
-                    eprintln!("[{err}]\n");
-                    agent.feed_tool_result(&call.name, &err);
-                }
+            EventPayload::ToolRequest { name, args, .. } => {
+                println!("\n[tool: {name} | args: {args}]");
+            }
+            EventPayload::ToolApprovalNeeded { .. } => {
+                print!("approve? (y)es / (n)o [reason] / (a)lways / (e)dit: ");
+                stdout.flush()?;
+            }
+            EventPayload::ToolResult { output, success, .. } => {
+                let preview = text::truncate(&output, 200);
+                println!("[result: {}]\n{}\n", if success { "ok" } else { "fail" }, preview);
             }
+            EventPayload::ContextCompacted { replaced } => {
+                println!("\n[context compacted: {replaced} messages summarised]");
+            }
+            EventPayload::FinalAnswer { .. } => println!("\n"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-            // Get follow-up response after tool result
-            print!("kova: ");
-            stdout.flush()?;
-            let mut writer = tokio::io::stdout();
-            match agent.send_stream("", &mut writer).await {
-                Ok(_) => {},
-                Err(_) => {
-                    if let Ok(r) = agent.send("").await {
-                        print!("{r}");
-                    }
-                }
+/// REPL state the slash commands work on.
+struct Cli {
+    agent: Agent,
+    /// Settings of the current model; `/model` swaps the name.
+    llm: LlmConfig,
+    session_dir: PathBuf,
+    /// `CliApprover`, or `AutoApprove` with `--yes`.
+    approver: Box<dyn Approver>,
+}
+
+impl Cli {
+    fn run_command(&mut self, command: Command) -> Result<()> {
+        match command {
+            Command::Help(topic) => println!("{}", commands::help(topic.as_deref())),
+            Command::Reset => {
+                self.agent.reset();
+                println!("[history cleared]");
+            }
+            Command::Model(None) => println!("model: {}", self.llm.model),
+            Command::Model(Some(model)) => {
+                let mut config = self.llm.clone();
+                config.model = model;
+                self.agent.set_llm(llm::from_config(config.clone())?);
+                println!("[model: {}]", config.model);
+                self.llm = config;
             }
-            println!("\n");
+            Command::Tools => print_tools(&self.agent.tools),
+            Command::Cd(None) => println!("cwd: {}", self.agent.tools.workspace().cwd().display()),
+            Command::Cd(Some(dir)) => match self.agent.tools.workspace_mut().set_cwd(&dir) {
+                Ok(()) => println!("[cwd: {}]", self.agent.tools.workspace().cwd().display()),
+                Err(e) => println!("{e}"),
+            },
+            Command::SessionList => print_sessions(&self.session_dir, self.agent.session().map(|s| s.id()))?,
+            Command::SessionLoad(id) => {
+                self.agent.set_session(open_session(&self.session_dir, &id)?)?;
+                println!("[session {id}: {} messages]", self.agent.history().len());
+            }
+            Command::SessionNew => {
+                let id = new_session_id(&self.session_dir);
+                self.agent.set_session(Session::new(&self.session_dir, &id)?)?;
+                println!("[session {id}]");
+            }
+            Command::Save(path) => {
+                std::fs::write(&path, markdown(self.agent.history()))?;
+                println!("[saved {} messages to {}]", self.agent.history().len(), path.display());
+            }
+            Command::Undo => match self.agent.undo() {
+                0 => println!("nothing to undo"),
+                dropped => println!("[dropped the last exchange, {dropped} messages]"),
+            },
+            Command::Exit => {}
         }
+        Ok(())
     }
+}
 
+/// The tools table of `/tools` and `kova-cli tools`.
+fn print_tools(tools: &ToolRegistry) {
+    let mut definitions = tools.definitions();
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+    println!("  {:<12} {:<14} {:<9} description", "tool", "policy (cli)", "approval");
+    for definition in definitions {
+        let needs_approval = tools.get(&definition.name).is_none_or(|t| t.needs_approval());
+        let (action, rules) = tools.policy().tool_default(&definition.name, needs_approval);
+        let policy = match rules {
+            0 => action.as_str().to_string(),
+            n => format!("{} +{n} rules", action.as_str()),
+        };
+        let flag = if needs_approval { "yes" } else { "no" };
+        let description = definition.description.lines().next().unwrap_or_default();
+        println!("  {:<12} {policy:<14} {flag:<9} {}", definition.name, text::truncate(description, 60));
+    }
+}
+
+/// An existing session of `session_dir`.
+fn open_session(session_dir: &Path, id: &str) -> Result<Session> {
+    if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+        anyhow::bail!("invalid session id: {id}");
+    }
+    let session = Session::new(session_dir, id)?;
+    if !session.exists() {
+        anyhow::bail!("no session {id} in {}", session_dir.display());
+    }
+    Ok(session)
+}
+
+/// The sessions table, most recent first; `current` is marked with `*`.
+fn print_sessions(session_dir: &Path, current: Option<&str>) -> Result<()> {
+    let sessions = Session::list(session_dir)?;
+    if let Some(current) = current.filter(|c| !sessions.iter().any(|s| s.id == *c)) {
+        println!("* {current:<20} (current, nothing saved yet)");
+    } else if sessions.is_empty() {
+        println!("no sessions in {}", session_dir.display());
+    }
+    for session in sessions {
+        let marker = if Some(session.id.as_str()) == current { "*" } else { " " };
+        println!(
+            "{marker} {:<20} {}  {:>4} messages  {}",
+            session.id,
+            session.modified.format("%Y-%m-%d %H:%M"),
+            session.messages,
+            text::truncate(&session.first_line, 50),
+        );
+    }
     Ok(())
 }
 
-fn find_project_root() -> Result<PathBuf> {
-    if let Ok(root) = std::env::var("KOVACLAW_ROOT") {
-        return Ok(PathBuf::from(root));
+/// A timestamp id that is not taken yet in `session_dir`.
+fn new_session_id(session_dir: &Path) -> String {
+    let base = chrono::Utc::now().format("%Y%m%d_%H%M%S").to_string();
+    let mut id = base.clone();
+    let mut n = 1;
+    while session_dir.join(format!("{id}.jsonl")).exists() {
+        n += 1;
+        id = format!("{base}_{n}");
     }
-    let mut dir = std::env::current_dir()?;
-    loop {
-        if dir.join("config/kovaclaw.json").exists() {
-            return Ok(dir);
+    id
+}
+
+/// The conversation as Markdown, for `/save`.
+fn markdown(history: &[Message]) -> String {
+    let mut out = String::new();
+    for m in history {
+        match m.role {
+            // The only system messages in a history are compaction summaries.
+            Role::System => out.push_str(&format!("## Earlier conversation\n\n{}\n\n", m.content)),
+            Role::User => out.push_str(&format!("## You\n\n{}\n\n", m.content)),
+            Role::Assistant => {
+                out.push_str("## Kova\n\n");
+                if !m.content.trim().is_empty() {
+                    out.push_str(&format!("{}\n\n", m.content.trim()));
+                }
+                for call in &m.tool_calls {
+                    out.push_str(&format!("Tool call: `{}` `{}`\n\n", call.name, call.arguments));
+                }
+            }
+            Role::Tool => {
+                let name = m.name.as_deref().unwrap_or("tool");
+                out.push_str(&format!("Result of `{name}`:\n\n```\n{}\n```\n\n", m.content.trim_end()));
+            }
         }
-        if !dir.pop() { break; }
     }
-    anyhow::bail!("kovaclaw.json not found. Set KOVACLAW_ROOT or run from the kovaclaw/ directory.")
+    out
+}
+
+/// Reads the answer to the prompt printed for `ToolApprovalNeeded`.
+struct CliApprover;
+
+#[async_trait]
+impl Approver for CliApprover {
+    async fn approve(&self, _call: &ToolCall, _needs_approval: bool) -> Approval {
+        let Some(answer) = read_stdin_line().await else {
+            return Approval::deny("no answer");
+        };
+        let (choice, rest) = answer.split_once(' ').unwrap_or((&answer, ""));
+        match choice {
+            "y" | "yes" => Approval::Approve,
+            "a" | "always" => Approval::ApproveAlways,
+            "e" | "edit" => {
+                print!("new arguments (JSON): ");
+                let _ = io::stdout().flush();
+                let Some(json) = read_stdin_line().await else {
+                    return Approval::deny("no arguments given");
+                };
+                match serde_json::from_str(&json) {
+                    Ok(arguments) => Approval::Edit { arguments },
+                    Err(e) => Approval::deny(format!("invalid arguments: {e}")),
+                }
+            }
+            _ if rest.trim().is_empty() => Approval::deny("denied by user"),
+            _ => Approval::deny(rest.trim()),
+        }
+    }
+}
+
+async fn read_stdin_line() -> Option<String> {
+    tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }).await.ok().flatten()
 }
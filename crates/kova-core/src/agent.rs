@@ -1,18 +1,31 @@
-use crate::event::{Message, Role};
-use crate::llm::LlmClient;
-use crate::session::Session;
-use crate::tools::{ToolCall, ToolRegistry};
+use crate::approval::{Approval, Approver};
+use crate::context::{self, TokenCounter};
+use crate::event::{Event, EventPayload, Message, Role};
+use crate::llm::{LlmProvider, LlmResponse};
+use crate::policy::PolicyAction;
+use crate::session::{Compaction, Session};
+use crate::tools::{ToolCall, ToolDef, ToolRegistry};
 use anyhow::Result;
+use async_stream::try_stream;
+use futures::{Stream, StreamExt};
+use std::collections::{HashSet, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use tokio::io::AsyncWrite;
+use tokio::sync::mpsc;
 
 const MAX_TOOL_ROUNDS: usize = 10;
 
 pub struct Agent {
-    llm: LlmClient,
+    llm: Box<dyn LlmProvider>,
+    native_tools: bool,
     system_prompt: String,
     history: Vec<Message>,
     pub tools: ToolRegistry,
     session: Option<Session>,
+    /// Tools the approver answered `ApproveAlways` for in this session.
+    always_approved: HashSet<String>,
+    tokens: TokenCounter,
 }
 
 pub struct LoopResult {
@@ -28,27 +41,80 @@ pub struct ToolExecution {
 }
 
 impl Agent {
-    pub fn new(llm: LlmClient, system_prompt: String) -> Self {
+    pub fn new(llm: Box<dyn LlmProvider>, system_prompt: String) -> Self {
         let mut tools = ToolRegistry::new();
         tools.register_defaults();
         Self {
+            native_tools: llm.native_tools(),
             llm,
             system_prompt,
             history: Vec::new(),
             tools,
             session: None,
+            always_approved: HashSet::new(),
+            tokens: TokenCounter::default(),
         }
     }
 
     pub fn with_session(mut self, session: Session) -> Result<Self> {
+        self.set_session(session)?;
+        Ok(self)
+    }
+
+    /// Switches to `session`, replacing the history with the one it recorded.
+    /// `ApproveAlways` answers do not carry over.
+    pub fn set_session(&mut self, session: Session) -> Result<()> {
         self.history = session.load()?;
         self.session = Some(session);
-        Ok(self)
+        self.always_approved.clear();
+        Ok(())
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Switches the model backend; the history is kept.
+    pub fn set_llm(&mut self, llm: Box<dyn LlmProvider>) {
+        self.native_tools = llm.native_tools();
+        self.llm = llm;
+        self.tokens = TokenCounter::default();
+    }
+
+    pub fn history(&self) -> &[Message] {
+        &self.history
     }
 
-    fn build_messages(&self) -> Vec<Message> {
+    /// Forgets the conversation. The session records it, so it stays forgotten on reload.
+    pub fn reset(&mut self) {
+        self.rewind(0);
+    }
+
+    /// Drops the last exchange: the latest user message and everything after it.
+    /// Returns how many messages were dropped.
+    pub fn undo(&mut self) -> usize {
+        let Some(start) = self.history.iter().rposition(|m| matches!(m.role, Role::User)) else {
+            return 0;
+        };
+        let dropped = self.history.len() - start;
+        self.rewind(start);
+        dropped
+    }
+
+    fn rewind(&mut self, len: usize) {
+        if let Some(ref session) = self.session {
+            let _ = session.append_rewind(len);
+        }
+        self.history.truncate(len);
+    }
+
+    /// Builds the request messages. With `native_tools` the tool definitions travel in the
+    /// request's `tools` field; otherwise they are described in the system prompt and the
+    /// model is asked to answer with `<tool_call>` tags. A compaction summary at the start
+    /// of the history joins the system prompt: chat templates reject a second system message.
+    fn build_messages(&self, native_tools: bool) -> Vec<Message> {
         let tool_defs = self.tools.definitions();
-        let tools_desc = if tool_defs.is_empty() {
+        let tools_desc = if native_tools || tool_defs.is_empty() {
             String::new()
         } else {
             let tools_json = serde_json::to_string_pretty(&tool_defs).unwrap_or_default();
@@ -60,14 +126,76 @@ impl Agent {
             )
         };
 
-        let mut messages = vec![Message {
-            role: Role::System,
-            content: format!("{}{}", self.system_prompt, tools_desc),
-        }];
-        messages.extend(self.history.clone());
+        let summaries = self.history.iter().take_while(|m| matches!(m.role, Role::System)).count();
+        let mut system = format!("{}{}", self.system_prompt, tools_desc);
+        for summary in &self.history[..summaries] {
+            system.push_str("\n\n");
+            system.push_str(&summary.content);
+        }
+        let mut messages = vec![Message::new(Role::System, system)];
+        messages.extend(self.history[summaries..].iter().cloned());
         messages
     }
 
+    /// Builds the request messages and fits them into the prompt budget: old tool outputs
+    /// are trimmed first, then older turns are summarised. Returns how many history
+    /// messages a compaction replaced, if one ran.
+    async fn context_messages(&mut self, native_tools: bool, tools: &[ToolDef]) -> Result<(Vec<Message>, Option<usize>)> {
+        let mut messages = self.build_messages(native_tools);
+        if self.fit(&mut messages, tools) {
+            return Ok((messages, None));
+        }
+
+        let pinned = context::pinned_start(&self.history);
+        if pinned == 0 {
+            tracing::warn!("latest turns alone exceed the prompt budget of {} tokens", self.llm.prompt_budget());
+            return Ok((messages, None));
+        }
+        self.compact(pinned).await?;
+
+        let mut messages = self.build_messages(native_tools);
+        if !self.fit(&mut messages, tools) {
+            tracing::warn!("context still exceeds the prompt budget after compaction");
+        }
+        Ok((messages, Some(pinned)))
+    }
+
+    /// Trims tool outputs from before the latest round until the request fits.
+    fn fit(&self, messages: &mut [Message], tools: &[ToolDef]) -> bool {
+        let budget = self.llm.prompt_budget();
+        let latest_round = messages.iter().rposition(|m| matches!(m.role, Role::Assistant)).unwrap_or(0);
+        context::trim_tool_outputs(messages, latest_round, |m| self.tokens.estimate(m, tools) <= budget)
+    }
+
+    /// Summarises `history[..end]` through the model and records the compaction. A
+    /// transcript too long for one request is summarised slice by slice, each request
+    /// carrying the summary so far, so every request fits the prompt budget.
+    async fn compact(&mut self, end: usize) -> Result<()> {
+        let prompt = Message::new(Role::System, context::SUMMARY_PROMPT);
+        let framing = [prompt.clone(), Message::new(Role::User, format!("{}\n\n\n", context::SUMMARY_HEADER))];
+        let framing = self.tokens.estimate(&framing, &[]);
+        // Half for the slice, half for the summary of the slices before it.
+        let room = self.tokens.chars(self.llm.prompt_budget().saturating_sub(framing)) / 2;
+        let mut summary = String::new();
+        for slice in context::transcript_slices(&self.history[..end], room) {
+            let text = if summary.is_empty() {
+                slice
+            } else {
+                format!("{}\n{summary}\n\n{slice}", context::SUMMARY_HEADER)
+            };
+            let request = [prompt.clone(), Message::new(Role::User, text)];
+            let reply = clean_response(&self.llm.chat(&request).await?);
+            summary = context::head(&reply, room).to_string();
+        }
+        let compaction = Compaction { summary, replaced: end };
+        if let Some(ref session) = self.session {
+            let _ = session.append_compaction(&compaction);
+        }
+        compaction.apply(&mut self.history);
+        tracing::info!("compacted {end} history messages into a summary");
+        Ok(())
+    }
+
     fn append(&mut self, msg: Message) {
         if let Some(ref session) = self.session {
             let _ = session.append(&msg);
@@ -76,96 +204,224 @@ impl Agent {
     }
 
     pub async fn send(&mut self, user_input: &str) -> Result<String> {
-        self.append(Message { role: Role::User, content: user_input.to_string() });
-        let messages = self.build_messages();
+        self.append(Message::new(Role::User, user_input));
+        let (messages, _) = self.context_messages(false, &[]).await?;
         let response = self.llm.chat(&messages).await?;
-        self.append(Message { role: Role::Assistant, content: response.clone() });
+        self.append(Message::new(Role::Assistant, response.clone()));
         Ok(response)
     }
 
-    pub async fn send_stream<W: AsyncWrite + Unpin>(
+    pub async fn send_stream<W: AsyncWrite + Unpin + Send>(
         &mut self,
         user_input: &str,
         writer: &mut W,
     ) -> Result<String> {
-        self.append(Message { role: Role::User, content: user_input.to_string() });
-        let messages = self.build_messages();
-        let response = self.llm.chat_stream(&messages, None, writer).await?;
-        self.append(Message { role: Role::Assistant, content: response.clone() });
+        self.append(Message::new(Role::User, user_input));
+        let (messages, _) = self.context_messages(false, &[]).await?;
+        let response = self.llm.chat_stream(&messages, None, writer).await?.content;
+        self.append(Message::new(Role::Assistant, response.clone()));
         Ok(response)
     }
 
     /// Agent loop: send message, execute tool calls automatically, repeat until no more tool calls.
-    /// auto_approve_fn decides per-tool whether to auto-approve.
-    pub async fn run_loop<F>(
-        &mut self,
-        user_input: &str,
-        auto_approve: F,
-    ) -> Result<LoopResult>
-    where
-        F: Fn(&str) -> bool,
-    {
-        self.append(Message { role: Role::User, content: user_input.to_string() });
+    /// The approver decides per call whether to run it. Collects the event stream of `run_stream`.
+    pub async fn run_loop(&mut self, user_input: &str, approver: &dyn Approver) -> Result<LoopResult> {
+        let stream = self.run_stream(user_input, approver);
+        futures::pin_mut!(stream);
 
+        let mut final_text = String::new();
         let mut tool_log = Vec::new();
-
-        for _ in 0..MAX_TOOL_ROUNDS {
-            let messages = self.build_messages();
-            let response = self.llm.chat(&messages).await?;
-            self.append(Message { role: Role::Assistant, content: response.clone() });
-
-            let calls = Self::parse_tool_calls(&response);
-            if calls.is_empty() {
-                return Ok(LoopResult {
-                    final_text: clean_response(&response),
-                    tool_log,
-                });
+        let mut requested = VecDeque::new();
+        while let Some(event) = stream.next().await {
+            match event?.payload {
+                EventPayload::ToolRequest { args, .. } => requested.push_back(args),
+                EventPayload::ToolResult { name, output, success, .. } => {
+                    tool_log.push(ToolExecution {
+                        name,
+                        args: requested.pop_front().unwrap_or_default(),
+                        output,
+                        success,
+                    });
+                }
+                EventPayload::FinalAnswer { text } => final_text = text,
+                _ => {}
             }
+        }
 
-            for call in &calls {
-                if !auto_approve(&call.name) {
-                    self.feed_tool_result(&call.name, "Tool call denied (not in whitelist).");
-                    tool_log.push(ToolExecution {
+        Ok(LoopResult { final_text, tool_log })
+    }
+
+    /// Streaming agent loop. Yields text deltas while the model talks, then one
+    /// `ToolRequest` / `ToolApprovalNeeded` / `ToolResult` group per call, a
+    /// `RoundFinished` after each tool round and a closing `FinalAnswer`.
+    ///
+    /// The approver runs only when the stream is polled past `ToolApprovalNeeded`,
+    /// so a frontend can render the prompt before the answer is read.
+    pub fn run_stream<'a>(
+        &'a mut self,
+        user_input: &'a str,
+        approver: &'a dyn Approver,
+    ) -> impl Stream<Item = Result<Event>> + Send + 'a {
+        self.run_stream_message(Message::new(Role::User, user_input), approver)
+    }
+
+    /// `run_stream` for a prepared user message, e.g. one with images attached.
+    pub fn run_stream_message<'a>(
+        &'a mut self,
+        message: Message,
+        approver: &'a dyn Approver,
+    ) -> impl Stream<Item = Result<Event>> + Send + 'a {
+        try_stream! {
+            self.append(message);
+
+            let mut final_text = None;
+            for round in 1..=MAX_TOOL_ROUNDS {
+                let native = self.native_tools;
+                let tool_defs = self.tools.definitions();
+                // OpenAI rejects an empty `tools` list; a registry without tools sends none.
+                let tools = (native && !tool_defs.is_empty()).then_some(tool_defs.as_slice());
+                let (messages, compacted) = self.context_messages(native, tools.unwrap_or_default()).await?;
+                if let Some(replaced) = compacted {
+                    yield Event::new(EventPayload::ContextCompacted { replaced });
+                }
+
+                let (delta_tx, mut delta_rx) = mpsc::unbounded_channel();
+                let mut writer = DeltaWriter::new(delta_tx);
+                let mut streamed = false;
+                let streamed_reply = {
+                    let request = self.llm.chat_stream(&messages, tools, &mut writer);
+                    tokio::pin!(request);
+                    loop {
+                        let step = tokio::select! {
+                            Some(text) = delta_rx.recv() => StreamStep::Delta(text),
+                            reply = &mut request => StreamStep::Done(reply),
+                        };
+                        match step {
+                            StreamStep::Delta(text) => {
+                                streamed = true;
+                                yield Event::new(EventPayload::TextDelta { text });
+                            }
+                            StreamStep::Done(reply) => break reply,
+                        }
+                    }
+                };
+                while let Ok(text) = delta_rx.try_recv() {
+                    streamed = true;
+                    yield Event::new(EventPayload::TextDelta { text });
+                }
+
+                let reply = match streamed_reply {
+                    Ok(reply) => reply,
+                    // Nothing reached the user yet, so retry without streaming.
+                    Err(e) if !streamed => {
+                        tracing::warn!("stream failed, retrying without streaming: {e}");
+                        let reply = self.llm.chat_with_tools(&messages, tools).await?;
+                        if !reply.content.is_empty() {
+                            yield Event::new(EventPayload::TextDelta { text: reply.content.clone() });
+                        }
+                        reply
+                    }
+                    Err(e) => Err(e)?,
+                };
+                if let Some(prompt_tokens) = reply.prompt_tokens {
+                    self.tokens.calibrate(&messages, tools.unwrap_or_default(), prompt_tokens);
+                }
+
+                let calls = if native {
+                    self.append(Message::assistant_with_calls(reply.content.clone(), reply.tool_calls.clone()));
+                    reply.tool_calls
+                } else {
+                    self.append(Message::new(Role::Assistant, reply.content.clone()));
+                    Self::parse_tool_calls(&reply.content)
+                };
+
+                if calls.is_empty() {
+                    final_text = Some(clean_response(&reply.content));
+                    break;
+                }
+
+                // Approvals are asked one by one; the approved calls then run concurrently.
+                let mut reviewed = Vec::with_capacity(calls.len());
+                for call in &calls {
+                    yield Event::new(EventPayload::ToolRequest {
+                        id: call.id.clone(),
                         name: call.name.clone(),
                         args: call.arguments.clone(),
-                        output: "denied".into(),
-                        success: false,
                     });
-                    continue;
-                }
 
-                match self.tools.execute(call) {
-                    Ok(result) => {
-                        self.feed_tool_result(&call.name, &result.output);
-                        tool_log.push(ToolExecution {
-                            name: call.name.clone(),
-                            args: call.arguments.clone(),
-                            output: result.output,
-                            success: result.success,
-                        });
-                    }
-                    Err(e) => {
-                        let err = format!("Error: {e}");
-                        self.feed_tool_result(&call.name, &err);
-                        tool_log.push(ToolExecution {
+                    let decision = self.tools.decide(call);
+                    let needs_approval = decision == PolicyAction::Ask
+                        && !self.always_approved.contains(&call.name);
+                    if needs_approval {
+                        yield Event::new(EventPayload::ToolApprovalNeeded {
+                            id: call.id.clone(),
                             name: call.name.clone(),
                             args: call.arguments.clone(),
-                            output: err,
-                            success: false,
                         });
                     }
+
+                    let mut call = call.clone();
+                    let verdict = match decision {
+                        PolicyAction::Deny => Err("blocked by policy".to_string()),
+                        PolicyAction::Allow => Ok(String::new()),
+                        PolicyAction::Ask if !needs_approval => Ok(String::new()),
+                        PolicyAction::Ask => {
+                            let flagged = self.tools.get(&call.name).is_some_and(|t| t.needs_approval());
+                            match approver.approve(&call, flagged).await {
+                                Approval::Approve => Ok(String::new()),
+                                Approval::ApproveAlways => {
+                                    self.always_approved.insert(call.name.clone());
+                                    Ok(String::new())
+                                }
+                                Approval::Edit { arguments } => {
+                                    let note = format!("[arguments edited by user: {arguments}]\n");
+                                    call.arguments = arguments;
+                                    Ok(note)
+                                }
+                                Approval::Deny { reason } => Err(reason),
+                            }
+                        }
+                    };
+                    reviewed.push((call, verdict));
                 }
+
+                // The registry re-checks the policy, so edited arguments can't escape a deny rule.
+                let batch: Vec<(ToolCall, bool)> = reviewed.iter()
+                    .filter(|(_, verdict)| verdict.is_ok())
+                    .map(|(call, _)| (call.clone(), true))
+                    .collect();
+                let mut results = self.tools.execute_all(&batch).await.into_iter();
+
+                for (call, verdict) in reviewed {
+                    let (output, success) = match verdict {
+                        Err(reason) => {
+                            tracing::warn!("{} call denied: {reason}", call.name);
+                            (format!("Tool call denied: {reason}"), false)
+                        }
+                        Ok(note) => match results.next() {
+                            Some(Ok(result)) => (format!("{note}{}", result.output), result.success),
+                            Some(Err(e)) => (format!("{note}Error: {e}"), false),
+                            None => (format!("{note}Error: no result"), false),
+                        },
+                    };
+                    self.feed_tool_result(&call, &output);
+                    yield Event::new(EventPayload::ToolResult {
+                        id: call.id.clone(),
+                        name: call.name.clone(),
+                        output,
+                        success,
+                    });
+                }
+                // Loop continues: LLM gets tool results and responds again
+                yield Event::new(EventPayload::RoundFinished { round });
             }
-            // Loop continues: LLM gets tool results and responds again
-        }
 
-        // Hit max rounds
-        Ok(LoopResult {
-            final_text: "[max tool rounds reached]".into(),
-            tool_log,
-        })
+            let text = final_text.unwrap_or_else(|| "[max tool rounds reached]".into());
+            yield Event::new(EventPayload::FinalAnswer { text });
+        }
     }
 
+    /// Text-scraping fallback for models without native tool calling.
     pub fn parse_tool_calls(response: &str) -> Vec<ToolCall> {
         let mut calls = Vec::new();
 
@@ -208,12 +464,8 @@ impl Agent {
         calls
     }
 
-    pub fn feed_tool_result(&mut self, name: &str, output: &str) {
-        self.append(Message {
-            role: Role::User,
-            content: format!("<tool_result>\n{{\"name\": \"{name}\", \"output\": {}}}\n</tool_result>",
-                serde_json::to_string(output).unwrap_or_else(|_| format!("\"{output}\""))),
-        });
+    pub fn feed_tool_result(&mut self, call: &ToolCall, output: &str) {
+        self.append(Message::tool_result(call, output));
     }
 }
 
@@ -239,3 +491,116 @@ fn strip_tool_calls(text: &str) -> String {
     result.push_str(remaining);
     result.trim().to_string()
 }
+
+enum StreamStep {
+    Delta(String),
+    Done(Result<LlmResponse>),
+}
+
+/// `AsyncWrite` sink that forwards streamed text as complete UTF-8 strings.
+struct DeltaWriter {
+    tx: mpsc::UnboundedSender<String>,
+    pending: Vec<u8>,
+}
+
+impl DeltaWriter {
+    fn new(tx: mpsc::UnboundedSender<String>) -> Self {
+        Self { tx, pending: Vec::new() }
+    }
+}
+
+impl AsyncWrite for DeltaWriter {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        self.pending.extend_from_slice(buf);
+        // Hold back a trailing partial character until the rest arrives.
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        if valid > 0 {
+            let bytes: Vec<u8> = self.pending.drain(..valid).collect();
+            let _ = self.tx.send(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::LlmResponse;
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    const BUDGET: usize = 2000;
+
+    /// Records every request; summary requests get a long-winded summary back.
+    #[derive(Clone, Default)]
+    struct RecordingLlm {
+        requests: Arc<Mutex<Vec<Vec<Message>>>>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for RecordingLlm {
+        async fn chat_with_tools(&self, messages: &[Message], _tools: Option<&[ToolDef]>) -> Result<LlmResponse> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(messages.to_vec());
+            let content = if messages[0].content == context::SUMMARY_PROMPT {
+                format!("summary {} {}", requests.len(), "and so on ".repeat(2000))
+            } else {
+                "fine".into()
+            };
+            Ok(LlmResponse { content, ..Default::default() })
+        }
+
+        fn native_tools(&self) -> bool {
+            false
+        }
+
+        fn prompt_budget(&self) -> usize {
+            BUDGET
+        }
+    }
+
+    #[tokio::test]
+    async fn compacts_sessions_far_larger_than_the_window() {
+        let llm = RecordingLlm::default();
+        let mut agent = Agent::new(Box::new(llm.clone()), "You are kova.".into());
+        for i in 0..300 {
+            agent.history.push(Message::new(Role::User, format!("question {i} {}", "words ".repeat(100))));
+            agent.history.push(Message::new(Role::Assistant, format!("answer {i} {}", "words ".repeat(100))));
+        }
+        // One message alone larger than the window.
+        agent.history.insert(10, Message::new(Role::User, "paste ".repeat(20_000)));
+        let tokens = TokenCounter::default();
+        assert!(tokens.estimate(&agent.history, &[]) > 50 * BUDGET);
+
+        assert_eq!(agent.send("and now?").await.unwrap(), "fine");
+
+        let requests = llm.requests.lock().unwrap();
+        let summaries = requests.iter().filter(|r| r[0].content == context::SUMMARY_PROMPT).count();
+        assert!(summaries > 10, "{summaries} summary requests");
+        for request in requests.iter() {
+            assert!(tokens.estimate(request, &[]) <= BUDGET, "request of {} tokens", tokens.estimate(request, &[]));
+        }
+        // Later slices carry the summary so far.
+        assert!(requests[1][1].content.starts_with(&format!("{}\nsummary 1 ", context::SUMMARY_HEADER)));
+        assert!(agent.history[0].content.starts_with(&format!("{}\nsummary {summaries} ", context::SUMMARY_HEADER)));
+        assert_eq!(agent.history.len(), 1 + 3 + 1);
+        // The summary rides in the one system message; the conversation opens with the user.
+        let last = requests.last().unwrap();
+        assert!(last[0].content.starts_with("You are kova.\n\n"));
+        assert!(last[0].content.contains(&format!("{}\nsummary {summaries} ", context::SUMMARY_HEADER)));
+        assert_eq!(last.iter().filter(|m| matches!(m.role, Role::System)).count(), 1);
+        assert!(matches!(last[1].role, Role::User));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::tools::ToolCall;
+use async_trait::async_trait;
+use std::collections::HashSet;
+
+/// Answer to a tool approval request.
+#[derive(Debug, Clone)]
+pub enum Approval {
+    Approve,
+    Deny { reason: String },
+    /// Approve this call and every later call to the same tool in this session.
+    ApproveAlways,
+    /// Run the call with replaced arguments.
+    Edit { arguments: serde_json::Value },
+}
+
+impl Approval {
+    pub fn deny(reason: impl Into<String>) -> Self {
+        Approval::Deny { reason: reason.into() }
+    }
+}
+
+/// Decides whether the agent loop may run a tool call.
+///
+/// Consulted for calls the channel policy marks `ask`, with the full arguments and
+/// the tool's `needs_approval` flag. Calls the policy allows or denies never reach it.
+#[async_trait]
+pub trait Approver: Send + Sync {
+    async fn approve(&self, call: &ToolCall, needs_approval: bool) -> Approval;
+}
+
+/// Plain closures work as synchronous approvers.
+#[async_trait]
+impl<F> Approver for F
+where
+    F: Fn(&ToolCall, bool) -> Approval + Send + Sync,
+{
+    async fn approve(&self, call: &ToolCall, needs_approval: bool) -> Approval {
+        self(call, needs_approval)
+    }
+}
+
+/// Approves everything. For trusted, non-interactive runs.
+pub struct AutoApprove;
+
+#[async_trait]
+impl Approver for AutoApprove {
+    async fn approve(&self, _call: &ToolCall, _needs_approval: bool) -> Approval {
+        Approval::Approve
+    }
+}
+
+/// Approves tools by name and denies the rest.
+pub struct AllowList(pub HashSet<String>);
+
+impl AllowList {
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(names.into_iter().map(Into::into).collect())
+    }
+}
+
+#[async_trait]
+impl Approver for AllowList {
+    async fn approve(&self, call: &ToolCall, _needs_approval: bool) -> Approval {
+        if self.0.contains(&call.name) {
+            Approval::Approve
+        } else {
+            Approval::deny("not in whitelist")
+        }
+    }
+}
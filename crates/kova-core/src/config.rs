@@ -1,4 +1,7 @@
+use crate::policy::PolicyConfig;
+use crate::tools::workspace::Workspace;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize)]
@@ -7,23 +10,259 @@ pub struct Config {
     pub identity_path: PathBuf,
     #[serde(default = "default_session_dir")]
     pub session_dir: PathBuf,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
+    #[serde(default)]
+    pub shell: ShellConfig,
+    /// How many tool calls of one round may run at the same time.
+    #[serde(default = "default_max_parallel_tools")]
+    pub max_parallel_tools: usize,
+    #[serde(default)]
+    pub whatsapp: WhatsAppConfig,
 }
 
+/// Settings for the WhatsApp frontend.
 #[derive(Debug, Deserialize)]
+pub struct WhatsAppConfig {
+    /// A chat's agent is unloaded after this long without messages; its session stays on disk.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout_secs: u64,
+    /// Chats (contact or group JIDs) kova answers, with their role. Everyone else is unknown.
+    #[serde(default)]
+    pub senders: HashMap<String, SenderRole>,
+    #[serde(default)]
+    pub roles: HashMap<SenderRole, RoleConfig>,
+    /// Fixed reply for unknown senders; without one they are ignored.
+    #[serde(default)]
+    pub unknown_reply: Option<String>,
+    /// Where the bridge saves incoming media of the chats in `senders`. Relative paths
+    /// resolve against the project root.
+    #[serde(default = "default_media_dir")]
+    pub media_dir: PathBuf,
+    /// Larger attachments are neither downloaded nor sent.
+    #[serde(default = "default_max_media_bytes")]
+    pub max_media_bytes: u64,
+    /// Saved media older than this is deleted; 0 keeps it.
+    #[serde(default = "default_media_retention_days")]
+    pub media_retention_days: u64,
+    #[serde(default)]
+    pub groups: GroupsConfig,
+    #[serde(default)]
+    pub approvals: ApprovalsConfig,
+}
+
+/// When kova answers in group chats.
+#[derive(Debug, Deserialize)]
+pub struct GroupsConfig {
+    /// Messages starting with this address kova, besides @-mentions and replies to it.
+    #[serde(default = "default_group_prefix")]
+    pub prefix: String,
+    /// Other recent group messages handed to the agent as context with the next one it answers.
+    #[serde(default = "default_group_context")]
+    pub context_messages: usize,
+}
+
+impl Default for GroupsConfig {
+    fn default() -> Self {
+        Self { prefix: default_group_prefix(), context_messages: default_group_context() }
+    }
+}
+
+/// Approval of `ask` tool calls by the owner over WhatsApp.
+#[derive(Debug, Deserialize)]
+pub struct ApprovalsConfig {
+    /// Where approval requests go; defaults to the first `owner` in `senders`.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Unanswered requests are denied after this long.
+    #[serde(default = "default_approval_timeout")]
+    pub timeout_secs: u64,
+}
+
+impl Default for ApprovalsConfig {
+    fn default() -> Self {
+        Self { owner: None, timeout_secs: default_approval_timeout() }
+    }
+}
+
+impl Default for WhatsAppConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: default_idle_timeout(),
+            senders: HashMap::new(),
+            roles: HashMap::new(),
+            unknown_reply: None,
+            media_dir: default_media_dir(),
+            max_media_bytes: default_max_media_bytes(),
+            media_retention_days: default_media_retention_days(),
+            groups: GroupsConfig::default(),
+            approvals: ApprovalsConfig::default(),
+        }
+    }
+}
+
+impl WhatsAppConfig {
+    /// Settings of `role`; roles missing from the config get the built-in defaults.
+    pub fn role(&self, role: SenderRole) -> RoleConfig {
+        self.roles.get(&role).cloned().unwrap_or_else(|| RoleConfig::default_for(role))
+    }
+
+    /// The JID approval requests go to, if there is one.
+    pub fn approval_owner(&self) -> Option<String> {
+        if let Some(owner) = &self.approvals.owner {
+            return Some(owner.clone());
+        }
+        let mut owners: Vec<&String> = self.senders.iter()
+            .filter(|(_, role)| **role == SenderRole::Owner)
+            .map(|(jid, _)| jid)
+            .collect();
+        owners.sort();
+        owners.first().map(|jid| jid.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderRole {
+    Owner,
+    Trusted,
+    Guest,
+}
+
+impl SenderRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SenderRole::Owner => "owner",
+            SenderRole::Trusted => "trusted",
+            SenderRole::Guest => "guest",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoleConfig {
+    /// Policy channel applied to the role's chats; without one the role gets no tools.
+    #[serde(default)]
+    pub policy: Option<String>,
+    /// Messages per hour per chat; `None` is unlimited.
+    #[serde(default)]
+    pub messages_per_hour: Option<u32>,
+}
+
+impl RoleConfig {
+    fn default_for(role: SenderRole) -> Self {
+        match role {
+            SenderRole::Owner => Self { policy: Some("whatsapp".into()), messages_per_hour: None },
+            SenderRole::Trusted => Self { policy: Some("whatsapp".into()), messages_per_hour: Some(60) },
+            SenderRole::Guest => Self { policy: None, messages_per_hour: Some(10) },
+        }
+    }
+}
+
+/// Limits for `shell_exec`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShellConfig {
+    #[serde(default = "default_shell_timeout")]
+    pub default_timeout_secs: u64,
+    /// Upper bound for the per-call `timeout_secs` argument.
+    #[serde(default = "default_shell_max_timeout")]
+    pub max_timeout_secs: u64,
+    /// Per stream; longer output keeps its head and tail.
+    #[serde(default = "default_shell_output")]
+    pub max_output_bytes: usize,
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        Self {
+            default_timeout_secs: default_shell_timeout(),
+            max_timeout_secs: default_shell_max_timeout(),
+            max_output_bytes: default_shell_output(),
+        }
+    }
+}
+
+/// Where file tools may read and write. Relative entries resolve against the project root.
+#[derive(Debug, Deserialize)]
+pub struct WorkspaceConfig {
+    #[serde(default = "default_workspace_roots")]
+    pub roots: Vec<PathBuf>,
+    /// Working directory for relative paths; defaults to the first root.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        Self { roots: default_workspace_roots(), cwd: None }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct LlmConfig {
+    #[serde(default)]
+    pub provider: Provider,
     pub base_url: String,
+    /// API key for hosted backends. Anthropic falls back to `ANTHROPIC_API_KEY`.
+    #[serde(default)]
+    pub api_key: Option<String>,
     #[serde(default = "default_model")]
     pub model: String,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: u32,
+    /// Model context size in tokens; the prompt gets what `max_tokens` leaves over.
+    #[serde(default = "default_context_window")]
+    pub context_window: u32,
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    /// Send tools through the OpenAI `tools` field. Disable for models that
+    /// can't do native tool calling; the agent then falls back to `<tool_call>` tags.
+    #[serde(default = "default_native_tools")]
+    pub native_tools: bool,
+    /// The model accepts images. Without it, attached images reach the model only as file paths.
+    #[serde(default)]
+    pub vision: bool,
+}
+
+impl LlmConfig {
+    pub fn prompt_budget(&self) -> usize {
+        self.context_window.saturating_sub(self.max_tokens) as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// OpenAI-compatible `/v1/chat/completions` (llama-server, vLLM, OpenAI).
+    #[default]
+    #[serde(alias = "openai-compatible")]
+    OpenAi,
+    /// Ollama's native `/api/chat`.
+    Ollama,
+    /// Anthropic Messages API (`/v1/messages`).
+    Anthropic,
 }
 
 fn default_model() -> String { "qwen2.5".into() }
 fn default_max_tokens() -> u32 { 4096 }
+fn default_context_window() -> u32 { 32768 }
 fn default_temperature() -> f32 { 0.7 }
+fn default_native_tools() -> bool { true }
 fn default_session_dir() -> PathBuf { "sessions".into() }
+fn default_shell_timeout() -> u64 { 60 }
+fn default_shell_max_timeout() -> u64 { 600 }
+fn default_shell_output() -> usize { 32 * 1024 }
+fn default_max_parallel_tools() -> usize { 4 }
+fn default_idle_timeout() -> u64 { 30 * 60 }
+fn default_media_dir() -> PathBuf { "media".into() }
+fn default_max_media_bytes() -> u64 { 20 * 1024 * 1024 }
+fn default_media_retention_days() -> u64 { 7 }
+fn default_group_prefix() -> String { "!kova".into() }
+fn default_group_context() -> usize { 20 }
+fn default_approval_timeout() -> u64 { 5 * 60 }
+fn default_workspace_roots() -> Vec<PathBuf> { vec![".".into()] }
 
 impl Config {
     pub fn load(path: &Path) -> anyhow::Result<Self> {
@@ -31,8 +270,44 @@ impl Config {
         Ok(serde_json::from_str(&content)?)
     }
 
+    pub fn workspace(&self, base_dir: &Path) -> anyhow::Result<Workspace> {
+        let roots: Vec<PathBuf> = self.workspace.roots.iter().map(|r| base_dir.join(r)).collect();
+        let cwd = self.workspace.cwd.as_ref().map(|c| base_dir.join(c));
+        Workspace::new(&roots, cwd.as_deref())
+    }
+
     pub fn load_identity(&self, base_dir: &Path) -> anyhow::Result<String> {
         let path = base_dir.join(&self.identity_path);
         Ok(std::fs::read_to_string(path)?)
     }
 }
+
+/// Where the config lives, relative to the project root.
+pub const CONFIG_FILE: &str = "config/kovaclaw.json";
+
+/// The project root: `KOVACLAW_ROOT` if set, otherwise the nearest directory at or
+/// above the current one that has a `CONFIG_FILE`.
+pub fn find_project_root() -> anyhow::Result<PathBuf> {
+    if let Ok(root) = std::env::var("KOVACLAW_ROOT") {
+        return Ok(PathBuf::from(root));
+    }
+    let mut dir = std::env::current_dir()?;
+    loop {
+        if dir.join(CONFIG_FILE).exists() {
+            return Ok(dir);
+        }
+        if !dir.pop() { break; }
+    }
+    anyhow::bail!("kovaclaw.json not found. Set KOVACLAW_ROOT or run from the kovaclaw/ directory.")
+}
+
+/// The project root of a config file given by path: the directory holding its
+/// `config/` directory, or else the file's own directory. Relative paths in the
+/// config resolve against it.
+pub fn project_root_of(config_file: &Path) -> PathBuf {
+    let dir = config_file.parent().unwrap_or(Path::new("."));
+    match dir.file_name() {
+        Some(name) if name == "config" => dir.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        _ => dir.to_path_buf(),
+    }
+}
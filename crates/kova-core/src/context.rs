@@ -0,0 +1,166 @@
+//! Keeps requests inside the model's context window.
+//!
+//! Tokens are estimated from character counts, calibrated against the prompt size
+//! the server reports. When a request would not fit, old tool outputs are trimmed
+//! first; if that is not enough the agent summarises older turns (see
+//! [`Agent`](crate::agent::Agent)) and records the compaction in the session.
+
+use crate::event::{Message, Role};
+use crate::tools::ToolDef;
+
+/// User turns kept verbatim when older history is summarised.
+pub const KEEP_TURNS: usize = 2;
+
+/// Trimmed tool outputs keep this many characters.
+const TRIMMED_OUTPUT_CHARS: usize = 400;
+
+/// Rough per-message cost of role markers and framing.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Rough cost of an attached image, in characters of text.
+const IMAGE_CHARS: usize = 3000;
+
+pub const SUMMARY_PROMPT: &str = "Summarise the conversation below so it can replace it in your memory. \
+Keep facts about the user, decisions, file paths, commands and their outcomes, and any open tasks. \
+Drop small talk and full tool outputs. If it starts with a summary of the earlier conversation, \
+fold that into yours. Answer with the summary only.";
+
+pub const SUMMARY_HEADER: &str = "Summary of the earlier conversation:";
+
+/// Character-based token estimate.
+pub struct TokenCounter {
+    chars_per_token: f64,
+}
+
+impl Default for TokenCounter {
+    fn default() -> Self {
+        Self { chars_per_token: 3.5 }
+    }
+}
+
+impl TokenCounter {
+    pub fn estimate(&self, messages: &[Message], tools: &[ToolDef]) -> usize {
+        let chars = count_chars(messages, tools);
+        (chars as f64 / self.chars_per_token).ceil() as usize + messages.len() * MESSAGE_OVERHEAD
+    }
+
+    /// Characters of text that make about `tokens` tokens.
+    pub fn chars(&self, tokens: usize) -> usize {
+        (tokens as f64 * self.chars_per_token) as usize
+    }
+
+    /// Adjusts the ratio to a request the server counted at `prompt_tokens`.
+    pub fn calibrate(&mut self, messages: &[Message], tools: &[ToolDef], prompt_tokens: u32) {
+        let overhead = messages.len() * MESSAGE_OVERHEAD;
+        let tokens = (prompt_tokens as usize).saturating_sub(overhead);
+        if tokens == 0 {
+            return;
+        }
+        let ratio = count_chars(messages, tools) as f64 / tokens as f64;
+        self.chars_per_token = ratio.clamp(1.0, 8.0);
+    }
+}
+
+fn count_chars(messages: &[Message], tools: &[ToolDef]) -> usize {
+    let messages: usize = messages.iter()
+        .map(|m| {
+            m.content.chars().count()
+                + m.images.len() * IMAGE_CHARS
+                + m.tool_calls.iter().map(|c| c.name.len() + c.arguments.to_string().len()).sum::<usize>()
+        })
+        .sum();
+    let tools: usize = tools.iter()
+        .map(|t| t.name.len() + t.description.len() + t.parameters.to_string().len())
+        .sum();
+    messages + tools
+}
+
+/// Index of the first history message that must stay verbatim: the start of the
+/// last [`KEEP_TURNS`] user turns.
+pub fn pinned_start(history: &[Message]) -> usize {
+    history.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, m)| matches!(m.role, Role::User))
+        .nth(KEEP_TURNS - 1)
+        .map_or(0, |(i, _)| i)
+}
+
+/// Trims tool outputs in `messages[..end]`, oldest first, until `fits` holds.
+/// Returns whether the messages fit afterwards.
+pub fn trim_tool_outputs(messages: &mut [Message], end: usize, fits: impl Fn(&[Message]) -> bool) -> bool {
+    for i in 0..end.min(messages.len()) {
+        if fits(messages) {
+            return true;
+        }
+        let m = &mut messages[i];
+        if !matches!(m.role, Role::Tool) || m.content.chars().count() <= TRIMMED_OUTPUT_CHARS {
+            continue;
+        }
+        let cut = head(&m.content, TRIMMED_OUTPUT_CHARS).len();
+        let dropped = m.content[cut..].chars().count();
+        m.content.truncate(cut);
+        m.content.push_str(&format!("\n[... {dropped} characters of old output trimmed]"));
+    }
+    fits(messages)
+}
+
+/// Plain-text transcript of `messages` for the summary request.
+pub fn transcript(messages: &[Message]) -> String {
+    let mut out = String::new();
+    for m in messages {
+        let role = match m.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        };
+        out.push_str(role);
+        if let Some(name) = &m.name {
+            out.push_str(&format!(" ({name})"));
+        }
+        out.push_str(": ");
+        match m.role {
+            Role::Tool => out.push_str(head(&m.content, TRIMMED_OUTPUT_CHARS)),
+            _ => out.push_str(&m.content),
+        }
+        for call in &m.tool_calls {
+            out.push_str(&format!("\n[called {} {}]", call.name, call.arguments));
+        }
+        out.push_str("\n\n");
+    }
+    out
+}
+
+/// The transcript of `messages` in pieces of at most `max_chars` characters, split
+/// between messages. A message longer than that on its own is cut.
+pub fn transcript_slices(messages: &[Message], max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(TRIMMED_OUTPUT_CHARS);
+    let mut slices = Vec::new();
+    let mut slice = String::new();
+    let mut slice_chars = 0;
+    for m in messages {
+        let mut entry = transcript(std::slice::from_ref(m));
+        let mut chars = entry.chars().count();
+        if chars > max_chars {
+            entry = format!("{}\n\n", head(&entry, max_chars - 2));
+            chars = max_chars;
+        }
+        if slice_chars + chars > max_chars && !slice.is_empty() {
+            slices.push(std::mem::take(&mut slice));
+            slice_chars = 0;
+        }
+        slice.push_str(&entry);
+        slice_chars += chars;
+    }
+    if !slice.is_empty() {
+        slices.push(slice);
+    }
+    slices
+}
+
+/// The first `chars` characters of `text`.
+pub(crate) fn head(text: &str, chars: usize) -> &str {
+    let end = text.char_indices().nth(chars).map_or(text.len(), |(i, _)| i);
+    &text[..end]
+}
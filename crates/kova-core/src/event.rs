@@ -1,4 +1,6 @@
+use crate::tools::ToolCall;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,8 +16,31 @@ pub enum EventPayload {
     MessageIn { source: Source, text: String },
     LlmRequest { messages: Vec<Message> },
     LlmResponse { content: String },
-    ToolRequest { name: String, args: serde_json::Value },
-    ToolResult { name: String, output: String, success: bool },
+    /// A chunk of assistant text as the model streams it.
+    TextDelta { text: String },
+    /// The model asked for a tool call; emitted before approval and execution.
+    ToolRequest {
+        #[serde(default)]
+        id: String,
+        name: String,
+        args: serde_json::Value,
+    },
+    /// The requested tool is flagged `needs_approval`; the loop consults its approver next.
+    ToolApprovalNeeded { id: String, name: String, args: serde_json::Value },
+    ToolResult {
+        #[serde(default)]
+        id: String,
+        name: String,
+        output: String,
+        success: bool,
+    },
+    /// Older turns were summarised to fit the context window; `replaced` history
+    /// messages became one summary message.
+    ContextCompacted { replaced: usize },
+    /// A tool round finished and its results were fed back to the model.
+    RoundFinished { round: usize },
+    /// The loop ended; `text` is the reply to show the user.
+    FinalAnswer { text: String },
     MessageOut { target: Source, text: String },
 }
 
@@ -29,6 +54,25 @@ pub enum Source {
 pub struct Message {
     pub role: Role,
     pub content: String,
+    /// Structured tool calls requested by an assistant message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Id of the call a `Role::Tool` message answers. `None` for calls scraped from text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Name of the tool that produced a `Role::Tool` message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Images attached to a user message. Only vision models get to see them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+}
+
+/// An image file on disk; sessions keep the path, requests carry the bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Image {
+    pub path: PathBuf,
+    pub mime_type: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +81,42 @@ pub enum Role {
     System,
     User,
     Assistant,
+    Tool,
+}
+
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            name: None,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn user_with_images(content: impl Into<String>, images: Vec<Image>) -> Self {
+        Self {
+            images,
+            ..Self::new(Role::User, content)
+        }
+    }
+
+    pub fn assistant_with_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::new(Role::Assistant, content)
+        }
+    }
+
+    pub fn tool_result(call: &ToolCall, output: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: (!call.id.is_empty()).then(|| call.id.clone()),
+            name: Some(call.name.clone()),
+            ..Self::new(Role::Tool, output)
+        }
+    }
 }
 
 impl Event {
@@ -1,6 +1,13 @@
 pub mod config;
+pub mod context;
 pub mod event;
 pub mod llm;
 pub mod agent;
+pub mod approval;
 pub mod tools;
+pub mod policy;
 pub mod session;
+pub mod text;
+
+#[cfg(test)]
+mod testing;
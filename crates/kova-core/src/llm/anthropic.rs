@@ -0,0 +1,261 @@
+use super::{check_status, image_base64, tool_result_text, LlmProvider, LlmResponse};
+use crate::config::LlmConfig;
+use crate::event::{Message, Role};
+use crate::tools::{ToolCall, ToolDef};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+const API_VERSION: &str = "2023-06-01";
+
+/// Anthropic Messages API (`/v1/messages`) backend.
+pub struct AnthropicProvider {
+    client: reqwest::Client,
+    config: LlmConfig,
+    api_key: String,
+}
+
+#[derive(Serialize)]
+struct MessagesRequest {
+    model: String,
+    max_tokens: u32,
+    temperature: f32,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    system: String,
+    messages: Vec<WireMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<WireTool>>,
+}
+
+#[derive(Serialize)]
+struct WireMessage {
+    role: &'static str,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    /// Block types we don't consume (e.g. `thinking`).
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ImageSource {
+    r#type: String,
+    media_type: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct WireTool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    input_tokens: u32,
+}
+
+impl AnthropicProvider {
+    pub fn new(config: LlmConfig) -> Result<Self> {
+        let api_key = config.api_key.clone()
+            .or_else(|| std::env::var("ANTHROPIC_API_KEY").ok())
+            .ok_or_else(|| anyhow::anyhow!("Anthropic provider needs llm.api_key or ANTHROPIC_API_KEY"))?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            config,
+            api_key,
+        })
+    }
+
+    /// Splits out the system prompt and folds history into alternating user/assistant
+    /// turns. Tool results travel as `tool_result` blocks inside a user turn.
+    fn build_request(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> MessagesRequest {
+        let mut system = Vec::new();
+        let mut wire: Vec<WireMessage> = Vec::new();
+
+        for m in messages {
+            let (role, blocks) = match m.role {
+                Role::System => {
+                    system.push(m.content.as_str());
+                    continue;
+                }
+                Role::User => {
+                    let mut blocks: Vec<ContentBlock> = m.images.iter()
+                        .filter(|_| self.config.vision)
+                        .filter_map(|image| Some(ContentBlock::Image {
+                            source: ImageSource {
+                                r#type: "base64".into(),
+                                media_type: image.mime_type.clone(),
+                                data: image_base64(image)?,
+                            },
+                        }))
+                        .collect();
+                    blocks.push(ContentBlock::Text { text: m.content.clone() });
+                    ("user", blocks)
+                }
+                Role::Assistant => {
+                    let mut blocks = Vec::new();
+                    if !m.content.is_empty() {
+                        blocks.push(ContentBlock::Text { text: m.content.clone() });
+                    }
+                    blocks.extend(m.tool_calls.iter().map(|c| ContentBlock::ToolUse {
+                        id: c.id.clone(),
+                        name: c.name.clone(),
+                        input: c.arguments.clone(),
+                    }));
+                    ("assistant", blocks)
+                }
+                Role::Tool => match &m.tool_call_id {
+                    Some(id) => ("user", vec![ContentBlock::ToolResult {
+                        tool_use_id: id.clone(),
+                        content: m.content.clone(),
+                    }]),
+                    None => ("user", vec![ContentBlock::Text { text: tool_result_text(m) }]),
+                },
+            };
+            if blocks.is_empty() {
+                continue;
+            }
+            match wire.last_mut() {
+                Some(last) if last.role == role => last.content.extend(blocks),
+                _ => wire.push(WireMessage { role, content: blocks }),
+            }
+        }
+
+        MessagesRequest {
+            model: self.config.model.clone(),
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+            system: system.join("\n\n"),
+            messages: wire,
+            tools: tools.map(|defs| {
+                defs.iter().map(|t| WireTool {
+                    name: t.name.clone(),
+                    description: t.description.clone(),
+                    input_schema: t.parameters.clone(),
+                }).collect()
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn native_tools(&self) -> bool {
+        self.config.native_tools
+    }
+
+    fn prompt_budget(&self) -> usize {
+        self.config.prompt_budget()
+    }
+
+    async fn chat_with_tools(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> Result<LlmResponse> {
+        let request = self.build_request(messages, tools);
+        let url = format!("{}/v1/messages", self.config.base_url.trim_end_matches('/'));
+        let resp = self.client.post(&url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&request)
+            .send()
+            .await?;
+        let resp = check_status(resp).await?;
+
+        let body: MessagesResponse = resp.json().await?;
+        let mut response = LlmResponse {
+            finish_reason: body.stop_reason,
+            prompt_tokens: body.usage.map(|u| u.input_tokens),
+            ..Default::default()
+        };
+        for block in body.content {
+            match block {
+                ContentBlock::Text { text } => response.content.push_str(&text),
+                ContentBlock::ToolUse { id, name, input } => {
+                    response.tool_calls.push(ToolCall { id, name, arguments: input });
+                }
+                ContentBlock::ToolResult { .. } | ContentBlock::Image { .. } | ContentBlock::Unknown => {}
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{llm_config, tool_conversation, MockResponse, MockServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sends_system_apart_and_tool_turns_merged() {
+        let server = MockServer::start(vec![MockResponse::json(json!({
+            "content": [
+                { "type": "thinking", "thinking": "hmm" },
+                { "type": "text", "text": "Reading it." },
+                { "type": "tool_use", "id": "toolu_1", "name": "read_file", "input": { "path": "b.txt" } },
+            ],
+            "stop_reason": "tool_use",
+            "usage": { "input_tokens": 120, "output_tokens": 9 },
+        }))]).await;
+        let mut config = llm_config(&server.url, "anthropic");
+        config.api_key = Some("test-key".into());
+        let provider = AnthropicProvider::new(config).unwrap();
+        let (messages, tools) = tool_conversation();
+        let response = provider.chat_with_tools(&messages, Some(&tools)).await.unwrap();
+
+        assert_eq!(response.content, "Reading it.");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.prompt_tokens, Some(120));
+        let calls: Vec<_> = response.tool_calls.iter().map(|c| (c.id.as_str(), c.name.as_str(), &c.arguments)).collect();
+        assert_eq!(calls, [("toolu_1", "read_file", &json!({ "path": "b.txt" }))]);
+
+        assert_eq!(server.header(0, "x-api-key").as_deref(), Some("test-key"));
+        assert_eq!(server.header(0, "anthropic-version").as_deref(), Some(API_VERSION));
+        let request = &server.requests()[0];
+        assert_eq!(request["system"], json!("You are kova."));
+        // Roles alternate: the tool results and the follow-up share one user turn.
+        assert_eq!(request["messages"], json!([
+            { "role": "user", "content": [{ "type": "text", "text": "What is in a.txt?" }] },
+            { "role": "assistant", "content": [
+                { "type": "text", "text": "Let me look." },
+                { "type": "tool_use", "id": "call_1", "name": "read_file", "input": { "path": "a.txt" } },
+                { "type": "tool_use", "id": "call_2", "name": "shell_exec", "input": { "command": "wc -l a.txt" } },
+            ] },
+            { "role": "user", "content": [
+                { "type": "tool_result", "tool_use_id": "call_1", "content": "hello" },
+                { "type": "tool_result", "tool_use_id": "call_2", "content": "1 a.txt" },
+                { "type": "text", "text": "And b.txt?" },
+            ] },
+        ]));
+        assert_eq!(request["tools"][0]["name"], json!("read_file"));
+        assert_eq!(request["tools"][0]["input_schema"], tools[0].parameters);
+    }
+}
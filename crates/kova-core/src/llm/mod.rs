@@ -0,0 +1,99 @@
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+pub mod sse;
+
+use crate::config::{LlmConfig, Provider};
+use crate::event::{Image, Message};
+use crate::tools::{ToolCall, ToolDef};
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+pub use anthropic::AnthropicProvider;
+pub use ollama::OllamaProvider;
+pub use openai::OpenAiProvider;
+
+/// A non-streaming completion: the text content plus any native tool calls.
+#[derive(Debug, Clone, Default)]
+pub struct LlmResponse {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    /// Why generation stopped (`stop`, `length`, `tool_calls`, ...), when the backend says.
+    pub finish_reason: Option<String>,
+    /// Prompt size in tokens as counted by the server, when it reports usage.
+    pub prompt_tokens: Option<u32>,
+}
+
+/// A chat backend. `Agent` only talks to models through this trait.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    async fn chat_with_tools(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> Result<LlmResponse>;
+
+    /// Whether tools should be sent natively rather than described in the prompt.
+    fn native_tools(&self) -> bool;
+
+    /// Tokens the prompt may use: the context window minus the reply reservation.
+    fn prompt_budget(&self) -> usize;
+
+    async fn chat(&self, messages: &[Message]) -> Result<String> {
+        Ok(self.chat_with_tools(messages, None).await?.content)
+    }
+
+    /// Streams text content into `writer` and returns the assembled response,
+    /// including any streamed tool calls. Backends without streaming support
+    /// write the whole completion once it arrives.
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolDef]>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<LlmResponse> {
+        let response = self.chat_with_tools(messages, tools).await?;
+        writer.write_all(response.content.as_bytes()).await?;
+        writer.flush().await?;
+        Ok(response)
+    }
+}
+
+/// Builds the provider selected by `config.provider`.
+pub fn from_config(config: LlmConfig) -> Result<Box<dyn LlmProvider>> {
+    Ok(match config.provider {
+        Provider::OpenAi => Box::new(OpenAiProvider::new(config)),
+        Provider::Ollama => Box::new(OllamaProvider::new(config)),
+        Provider::Anthropic => Box::new(AnthropicProvider::new(config)?),
+    })
+}
+
+/// Text form of a tool result for calls that were scraped from text and have no id.
+pub(crate) fn tool_result_text(m: &Message) -> String {
+    let name = m.name.as_deref().unwrap_or_default();
+    let output = serde_json::to_string(&m.content)
+        .unwrap_or_else(|_| format!("\"{}\"", m.content));
+    format!("<tool_result>\n{{\"name\": \"{name}\", \"output\": {output}}}\n</tool_result>")
+}
+
+/// Base64 of an attached image, or `None` (with a warning) if the file is gone.
+pub(crate) fn image_base64(image: &Image) -> Option<String> {
+    use base64::Engine;
+    match std::fs::read(&image.path) {
+        Ok(bytes) => Some(base64::engine::general_purpose::STANDARD.encode(bytes)),
+        Err(e) => {
+            tracing::warn!("leaving out image {}: {e}", image.path.display());
+            None
+        }
+    }
+}
+
+pub(crate) fn new_call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+pub(crate) async fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("LLM request failed ({}): {}", status, body);
+    }
+    Ok(resp)
+}
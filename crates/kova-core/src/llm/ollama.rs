@@ -0,0 +1,200 @@
+use super::{check_status, image_base64, new_call_id, tool_result_text, LlmProvider, LlmResponse};
+use crate::config::LlmConfig;
+use crate::event::{Message, Role};
+use crate::tools::{ToolCall, ToolDef};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// Ollama's native `/api/chat` backend.
+pub struct OllamaProvider {
+    client: reqwest::Client,
+    config: LlmConfig,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    options: Options,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Serialize)]
+struct Options {
+    temperature: f32,
+    num_predict: u32,
+    /// Without it Ollama uses its own small default and silently cuts longer prompts.
+    num_ctx: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<WireToolCall>,
+    /// Base64 images for vision models.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireToolCall {
+    function: WireFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireFunctionCall {
+    name: String,
+    /// Ollama sends and expects arguments as a JSON object, not a string.
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ChatMessage,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+}
+
+impl OllamaProvider {
+    pub fn new(config: LlmConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    fn to_wire(m: &Message, vision: bool) -> ChatMessage {
+        let (role, content) = match m.role {
+            Role::System => ("system", m.content.clone()),
+            Role::User => ("user", m.content.clone()),
+            Role::Assistant => ("assistant", m.content.clone()),
+            Role::Tool if m.tool_call_id.is_some() => ("tool", m.content.clone()),
+            Role::Tool => ("user", tool_result_text(m)),
+        };
+        ChatMessage {
+            role: role.into(),
+            content,
+            tool_calls: m.tool_calls.iter().map(|c| WireToolCall {
+                function: WireFunctionCall {
+                    name: c.name.clone(),
+                    arguments: c.arguments.clone(),
+                },
+            }).collect(),
+            images: m.images.iter().filter(|_| vision).filter_map(image_base64).collect(),
+        }
+    }
+
+    fn build_request(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> ChatRequest {
+        ChatRequest {
+            model: self.config.model.clone(),
+            messages: messages.iter().map(|m| Self::to_wire(m, self.config.vision)).collect(),
+            stream: false,
+            options: Options {
+                temperature: self.config.temperature,
+                num_predict: self.config.max_tokens,
+                num_ctx: self.config.context_window,
+            },
+            tools: tools.map(|defs| {
+                defs.iter().map(|t| serde_json::json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name,
+                        "description": t.description,
+                        "parameters": t.parameters,
+                    }
+                })).collect()
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn native_tools(&self) -> bool {
+        self.config.native_tools
+    }
+
+    fn prompt_budget(&self) -> usize {
+        self.config.prompt_budget()
+    }
+
+    async fn chat_with_tools(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> Result<LlmResponse> {
+        let request = self.build_request(messages, tools);
+        let url = format!("{}/api/chat", self.config.base_url.trim_end_matches('/'));
+        let resp = self.client.post(&url).json(&request).send().await?;
+        let resp = check_status(resp).await?;
+
+        let chat_resp: ChatResponse = resp.json().await?;
+        // Ollama does not assign call ids, so we mint our own to pair results with calls.
+        let tool_calls = chat_resp.message.tool_calls.into_iter().map(|c| ToolCall {
+            id: new_call_id(),
+            name: c.function.name,
+            arguments: match c.function.arguments {
+                serde_json::Value::Null => serde_json::json!({}),
+                other => other,
+            },
+        }).collect();
+        Ok(LlmResponse {
+            content: chat_resp.message.content,
+            tool_calls,
+            finish_reason: chat_resp.done_reason,
+            prompt_tokens: chat_resp.prompt_eval_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{llm_config, tool_conversation, MockResponse, MockServer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn sends_arguments_as_objects_and_mints_call_ids() {
+        let server = MockServer::start(vec![MockResponse::json(json!({
+            "message": { "role": "assistant", "content": "", "tool_calls": [
+                { "function": { "name": "read_file", "arguments": { "path": "b.txt" } } },
+                { "function": { "name": "list_files" } },
+            ] },
+            "done_reason": "stop",
+            "prompt_eval_count": 77,
+        }))]).await;
+        let provider = OllamaProvider::new(llm_config(&server.url, "ollama"));
+        let (messages, tools) = tool_conversation();
+        let response = provider.chat_with_tools(&messages, Some(&tools)).await.unwrap();
+
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.prompt_tokens, Some(77));
+        let calls: Vec<_> = response.tool_calls.iter().map(|c| (c.name.as_str(), &c.arguments)).collect();
+        assert_eq!(calls, [("read_file", &json!({ "path": "b.txt" })), ("list_files", &json!({}))]);
+        assert!(response.tool_calls.iter().all(|c| c.id.starts_with("call_")));
+        assert_ne!(response.tool_calls[0].id, response.tool_calls[1].id);
+
+        let request = &server.requests()[0];
+        assert_eq!(request["stream"], json!(false));
+        let config = llm_config(&server.url, "ollama");
+        assert_eq!(request["options"]["num_ctx"], json!(config.context_window));
+        assert_eq!(request["options"]["num_predict"], json!(config.max_tokens));
+        assert_eq!(request["messages"], json!([
+            { "role": "system", "content": "You are kova." },
+            { "role": "user", "content": "What is in a.txt?" },
+            { "role": "assistant", "content": "Let me look.", "tool_calls": [
+                { "function": { "name": "read_file", "arguments": { "path": "a.txt" } } },
+                { "function": { "name": "shell_exec", "arguments": { "command": "wc -l a.txt" } } },
+            ] },
+            { "role": "tool", "content": "hello" },
+            { "role": "tool", "content": "1 a.txt" },
+            { "role": "user", "content": "And b.txt?" },
+        ]));
+        assert_eq!(request["tools"][0]["function"]["name"], json!("read_file"));
+    }
+}
@@ -0,0 +1,555 @@
+use super::sse::SseDecoder;
+use super::{check_status, image_base64, new_call_id, tool_result_text, LlmProvider, LlmResponse};
+use crate::config::LlmConfig;
+use crate::event::{Message, Role};
+use crate::tools::{ToolCall, ToolDef};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// OpenAI-compatible `/v1/chat/completions` backend (llama-server, vLLM, OpenAI).
+pub struct OpenAiProvider {
+    client: reqwest::Client,
+    config: LlmConfig,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    max_tokens: u32,
+    temperature: f32,
+    stream: bool,
+    /// Asks for a final `usage` chunk when streaming.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<ToolSchema>>,
+}
+
+#[derive(Serialize)]
+struct ToolSchema {
+    r#type: String,
+    function: ToolFunction,
+}
+
+#[derive(Serialize)]
+struct ToolFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<WireContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<WireToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// Plain text, or text and image parts for vision models.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum WireContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ImageUrl {
+    url: String,
+}
+
+impl WireContent {
+    fn into_text(self) -> String {
+        match self {
+            WireContent::Text(text) => text,
+            WireContent::Parts(parts) => parts.into_iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct WireToolCall {
+    #[serde(default)]
+    id: String,
+    #[serde(default = "function_type")]
+    r#type: String,
+    function: WireFunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct WireFunctionCall {
+    name: String,
+    /// JSON-encoded string on the wire; some servers send an object instead.
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+fn function_type() -> String { "function".into() }
+
+impl From<&ToolCall> for WireToolCall {
+    fn from(call: &ToolCall) -> Self {
+        Self {
+            id: call.id.clone(),
+            r#type: function_type(),
+            function: WireFunctionCall {
+                name: call.name.clone(),
+                arguments: serde_json::Value::String(call.arguments.to_string()),
+            },
+        }
+    }
+}
+
+impl From<WireToolCall> for ToolCall {
+    fn from(call: WireToolCall) -> Self {
+        let arguments = match call.function.arguments {
+            serde_json::Value::String(s) if s.trim().is_empty() => serde_json::json!({}),
+            serde_json::Value::String(s) => {
+                serde_json::from_str(&s).unwrap_or(serde_json::Value::String(s))
+            }
+            serde_json::Value::Null => serde_json::json!({}),
+            other => other,
+        };
+        let id = if call.id.is_empty() {
+            new_call_id()
+        } else {
+            call.id
+        };
+        Self { id, name: call.function.name, arguments }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u32,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChatMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// A fragment of a streamed tool call. `index` identifies the call; `id` and
+/// `name` arrive once, `arguments` is spread across many deltas.
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: Option<usize>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct FunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl PartialToolCall {
+    fn apply(&mut self, delta: ToolCallDelta) {
+        if let Some(id) = delta.id {
+            self.id = id;
+        }
+        if let Some(function) = delta.function {
+            if let Some(name) = function.name {
+                self.name.push_str(&name);
+            }
+            if let Some(args) = function.arguments {
+                self.arguments.push_str(&args);
+            }
+        }
+    }
+
+    fn finish(self) -> ToolCall {
+        ToolCall::from(WireToolCall {
+            id: self.id,
+            r#type: function_type(),
+            function: WireFunctionCall {
+                name: self.name,
+                arguments: serde_json::Value::String(self.arguments),
+            },
+        })
+    }
+}
+
+impl OpenAiProvider {
+    pub fn new(config: LlmConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// Maps a history message to the OpenAI wire format. Tool results without a call id
+    /// come from text-scraped calls, so they go back as a `<tool_result>` user message.
+    /// Images are sent as data URLs when `vision` is on.
+    fn to_wire(m: &Message, vision: bool) -> ChatMessage {
+        let role = match m.role {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool if m.tool_call_id.is_some() => "tool",
+            Role::Tool => {
+                return ChatMessage {
+                    role: "user".into(),
+                    content: Some(WireContent::Text(tool_result_text(m))),
+                    tool_calls: Vec::new(),
+                    tool_call_id: None,
+                    name: None,
+                };
+            }
+        };
+        ChatMessage {
+            role: role.into(),
+            content: Self::content(m, vision),
+            tool_calls: m.tool_calls.iter().map(WireToolCall::from).collect(),
+            tool_call_id: m.tool_call_id.clone(),
+            name: m.tool_call_id.as_ref().and(m.name.clone()),
+        }
+    }
+
+    fn content(m: &Message, vision: bool) -> Option<WireContent> {
+        if m.content.is_empty() && !m.tool_calls.is_empty() {
+            return None;
+        }
+        let images: Vec<ContentPart> = m.images.iter()
+            .filter(|_| vision)
+            .filter_map(|image| {
+                let url = format!("data:{};base64,{}", image.mime_type, image_base64(image)?);
+                Some(ContentPart::ImageUrl { image_url: ImageUrl { url } })
+            })
+            .collect();
+        if images.is_empty() {
+            return Some(WireContent::Text(m.content.clone()));
+        }
+        let mut parts = vec![ContentPart::Text { text: m.content.clone() }];
+        parts.extend(images);
+        Some(WireContent::Parts(parts))
+    }
+
+    fn build_request(&self, messages: &[Message], tools: Option<&[ToolDef]>, stream: bool) -> ChatRequest {
+        let chat_messages = messages.iter().map(|m| Self::to_wire(m, self.config.vision)).collect();
+
+        let tool_schemas = tools.map(|defs| {
+            defs.iter().map(|t| ToolSchema {
+                r#type: "function".into(),
+                function: ToolFunction {
+                    name: t.name.clone(),
+                    description: t.description.clone(),
+                    parameters: t.parameters.clone(),
+                },
+            }).collect()
+        });
+
+        ChatRequest {
+            model: self.config.model.clone(),
+            messages: chat_messages,
+            max_tokens: self.config.max_tokens,
+            temperature: self.config.temperature,
+            stream,
+            stream_options: stream.then(|| serde_json::json!({ "include_usage": true })),
+            tools: tool_schemas,
+        }
+    }
+
+    /// A request to the completions endpoint, authorised when `api_key` is set.
+    fn post(&self) -> reqwest::RequestBuilder {
+        let url = format!("{}/v1/chat/completions", self.config.base_url.trim_end_matches('/'));
+        let request = self.client.post(url);
+        match &self.config.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn native_tools(&self) -> bool {
+        self.config.native_tools
+    }
+
+    fn prompt_budget(&self) -> usize {
+        self.config.prompt_budget()
+    }
+
+    async fn chat_with_tools(&self, messages: &[Message], tools: Option<&[ToolDef]>) -> Result<LlmResponse> {
+        let request = self.build_request(messages, tools, false);
+        let resp = self.post().json(&request).send().await?;
+        let resp = check_status(resp).await?;
+
+        let chat_resp: ChatResponse = resp.json().await?;
+        let prompt_tokens = chat_resp.usage.map(|u| u.prompt_tokens);
+        let choice = chat_resp.choices.into_iter().next()
+            .ok_or_else(|| anyhow::anyhow!("Empty response from LLM"))?;
+        Ok(LlmResponse {
+            content: choice.message.content.map(WireContent::into_text).unwrap_or_default(),
+            tool_calls: choice.message.tool_calls.into_iter().map(ToolCall::from).collect(),
+            finish_reason: choice.finish_reason,
+            prompt_tokens,
+        })
+    }
+
+    async fn chat_stream(
+        &self,
+        messages: &[Message],
+        tools: Option<&[ToolDef]>,
+        writer: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<LlmResponse> {
+        let request = self.build_request(messages, tools, true);
+        let resp = self.post().json(&request).send().await?;
+        let resp = check_status(resp).await?;
+
+        let mut response = LlmResponse::default();
+        let mut partial_calls: Vec<PartialToolCall> = Vec::new();
+        let mut decoder = SseDecoder::new();
+        let mut stream = resp.bytes_stream();
+
+        'read: loop {
+            let events = match stream.next().await {
+                Some(chunk) => decoder.feed(&chunk?),
+                None => {
+                    let tail = decoder.finish();
+                    if tail.is_none() { break; }
+                    tail.into_iter().collect()
+                }
+            };
+            for event in events {
+                let data = event.data.trim();
+                if data == "[DONE]" { break 'read; }
+                if event.event.as_deref() == Some("error") {
+                    anyhow::bail!("LLM stream error: {data}");
+                }
+                let parsed: StreamChunk = match serde_json::from_str(data) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        tracing::warn!("skipping unparseable stream chunk: {e}: {data}");
+                        continue;
+                    }
+                };
+                if let Some(usage) = parsed.usage {
+                    response.prompt_tokens = Some(usage.prompt_tokens);
+                }
+                for choice in parsed.choices {
+                    if let Some(content) = &choice.delta.content {
+                        response.content.push_str(content);
+                        writer.write_all(content.as_bytes()).await?;
+                        writer.flush().await?;
+                    }
+                    for delta in choice.delta.tool_calls {
+                        let index = delta.index.unwrap_or(partial_calls.len().saturating_sub(1));
+                        if partial_calls.len() <= index {
+                            partial_calls.resize_with(index + 1, PartialToolCall::default);
+                        }
+                        partial_calls[index].apply(delta);
+                    }
+                    if choice.finish_reason.is_some() {
+                        response.finish_reason = choice.finish_reason;
+                    }
+                }
+            }
+        }
+
+        response.tool_calls = partial_calls.into_iter()
+            .filter(|c| !c.name.is_empty())
+            .map(PartialToolCall::finish)
+            .collect();
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{llm_config, tool_conversation, MockResponse, MockServer};
+    use serde_json::json;
+
+    fn sse(chunks: &[serde_json::Value]) -> String {
+        chunks.iter().map(|c| format!("data: {c}\n\n")).collect()
+    }
+
+    #[tokio::test]
+    async fn sends_tool_turns_and_parses_tool_calls() {
+        let server = MockServer::start(vec![MockResponse::json(json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": null, "tool_calls": [
+                    { "id": "call_x", "type": "function", "function": { "name": "read_file", "arguments": "{\"path\":\"b.txt\"}" } },
+                    // Some servers send an object and leave out the id.
+                    { "function": { "name": "shell_exec", "arguments": { "command": "ls" } } },
+                ] },
+                "finish_reason": "tool_calls",
+            }],
+            "usage": { "prompt_tokens": 88 },
+        }))]).await;
+        let mut config = llm_config(&server.url, "openai");
+        config.api_key = Some("sk-test".into());
+        let provider = OpenAiProvider::new(config);
+        let (messages, tools) = tool_conversation();
+        let response = provider.chat_with_tools(&messages, Some(&tools)).await.unwrap();
+        assert_eq!(server.header(0, "authorization").as_deref(), Some("Bearer sk-test"));
+
+        assert_eq!(response.content, "");
+        assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(response.prompt_tokens, Some(88));
+        let calls: Vec<_> = response.tool_calls.iter().map(|c| (c.name.as_str(), &c.arguments)).collect();
+        assert_eq!(calls, [("read_file", &json!({ "path": "b.txt" })), ("shell_exec", &json!({ "command": "ls" }))]);
+        assert_eq!(response.tool_calls[0].id, "call_x");
+        assert!(response.tool_calls[1].id.starts_with("call_"));
+
+        let request = &server.requests()[0];
+        assert_eq!(request["stream"], json!(false));
+        assert!(request.get("stream_options").is_none());
+        assert_eq!(request["messages"], json!([
+            { "role": "system", "content": "You are kova." },
+            { "role": "user", "content": "What is in a.txt?" },
+            { "role": "assistant", "content": "Let me look.", "tool_calls": [
+                { "id": "call_1", "type": "function", "function": { "name": "read_file", "arguments": "{\"path\":\"a.txt\"}" } },
+                { "id": "call_2", "type": "function", "function": { "name": "shell_exec", "arguments": "{\"command\":\"wc -l a.txt\"}" } },
+            ] },
+            { "role": "tool", "content": "hello", "tool_call_id": "call_1", "name": "read_file" },
+            { "role": "tool", "content": "1 a.txt", "tool_call_id": "call_2", "name": "shell_exec" },
+            { "role": "user", "content": "And b.txt?" },
+        ]));
+        assert_eq!(request["tools"][0], json!({ "type": "function", "function": {
+            "name": "read_file", "description": "Read a file", "parameters": tools[0].parameters,
+        } }));
+    }
+
+    #[tokio::test]
+    async fn chat_stream_assembles_content_and_tool_calls_across_chunks() {
+        let mut body = sse(&[
+            json!({ "choices": [{ "delta": { "role": "assistant", "content": "Bakıyorum, " } }] }),
+            json!({ "choices": [{ "delta": { "content": "çok güzel 🚀" } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "id": "call_a", "function": { "name": "read_file", "arguments": "{\"pa" } },
+            ] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 1, "id": "call_b", "function": { "name": "shell_exec", "arguments": "" } },
+            ] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 0, "function": { "arguments": "th\":\"a.txt\"}" } }] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 1, "function": { "arguments": "{\"command\":" } }] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{ "index": 1, "function": { "arguments": "\"ls\"}" } }] } }] }),
+            json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+            json!({ "choices": [], "usage": { "prompt_tokens": 42 } }),
+        ]);
+        body.push_str("data: [DONE]\n\n");
+        // Anything after [DONE] is ignored.
+        body.push_str(&sse(&[json!({ "choices": [{ "delta": { "content": "late" } }] })]));
+
+        for size in [1, 5, 7, 64, body.len()] {
+            let server = MockServer::start(vec![MockResponse::stream("text/event-stream", &body, size)]).await;
+            let provider = OpenAiProvider::new(llm_config(&server.url, "openai"));
+            let mut written = Vec::new();
+            let response = provider.chat_stream(&[Message::new(Role::User, "hi")], None, &mut written).await.unwrap();
+
+            assert_eq!(response.content, "Bakıyorum, çok güzel 🚀", "chunk size {size}");
+            assert_eq!(String::from_utf8(written).unwrap(), response.content);
+            assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+            assert_eq!(response.prompt_tokens, Some(42));
+            let calls: Vec<_> = response.tool_calls.iter().map(|c| (c.id.as_str(), c.name.as_str(), &c.arguments)).collect();
+            assert_eq!(calls, [
+                ("call_a", "read_file", &json!({ "path": "a.txt" })),
+                ("call_b", "shell_exec", &json!({ "command": "ls" })),
+            ]);
+
+            let request = &server.requests()[0];
+            // Local servers need no key.
+            assert_eq!(server.header(0, "authorization"), None);
+            assert_eq!(request["stream"], json!(true));
+            assert_eq!(request["stream_options"], json!({ "include_usage": true }));
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_stream_ends_without_done() {
+        let body = sse(&[json!({ "choices": [{ "delta": { "content": "hi" }, "finish_reason": "stop" }] })]);
+        let server = MockServer::start(vec![MockResponse::stream("text/event-stream", body.trim_end(), 3)]).await;
+        let provider = OpenAiProvider::new(llm_config(&server.url, "openai"));
+        let response = provider.chat_stream(&[Message::new(Role::User, "hi")], None, &mut Vec::new()).await.unwrap();
+        assert_eq!(response.content, "hi");
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn chat_stream_fails_on_error_event() {
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"par\"}}]}\n\n\
+            event: error\ndata: {\"error\":{\"message\":\"model overloaded\"}}\n\n";
+        let server = MockServer::start(vec![MockResponse::stream("text/event-stream", body, 9)]).await;
+        let provider = OpenAiProvider::new(llm_config(&server.url, "openai"));
+        let error = provider.chat_stream(&[Message::new(Role::User, "hi")], None, &mut Vec::new()).await.unwrap_err();
+        assert!(error.to_string().contains("model overloaded"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn chat_stream_reports_http_errors() {
+        let server = MockServer::start(vec![MockResponse::json(json!({ "error": "bad model" })).status(404)]).await;
+        let provider = OpenAiProvider::new(llm_config(&server.url, "openai"));
+        let error = provider.chat_stream(&[Message::new(Role::User, "hi")], None, &mut Vec::new()).await.unwrap_err();
+        assert!(error.to_string().contains("404") && error.to_string().contains("bad model"), "{error}");
+    }
+}
@@ -0,0 +1,170 @@
+//! Incremental Server-Sent Events decoder.
+//!
+//! Bytes are buffered until a full line is available, so events split across
+//! TCP reads (including inside a multibyte UTF-8 character) decode correctly.
+
+/// One dispatched SSE event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    /// `data:` lines joined with `\n`.
+    pub data: String,
+}
+
+#[derive(Default)]
+pub struct SseDecoder {
+    buf: Vec<u8>,
+    pending: SseEvent,
+    has_data: bool,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds raw bytes and returns every event completed by them.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.buf.drain(..=pos).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if let Some(event) = self.process_line(&String::from_utf8_lossy(&line)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flushes a trailing event when the stream ends without a blank line.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            let line = String::from_utf8_lossy(&line);
+            if let Some(event) = self.process_line(line.trim_end_matches('\r')) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((f, v)) => (f, v.strip_prefix(' ').unwrap_or(v)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => {
+                if self.has_data {
+                    self.pending.data.push('\n');
+                }
+                self.pending.data.push_str(value);
+                self.has_data = true;
+            }
+            "event" => self.pending.event = Some(value.to_string()),
+            "id" => self.pending.id = Some(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        if !self.has_data {
+            self.pending = SseEvent::default();
+            return None;
+        }
+        self.has_data = false;
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event: Option<&str>, id: Option<&str>, data: &str) -> SseEvent {
+        SseEvent { event: event.map(String::from), id: id.map(String::from), data: data.into() }
+    }
+
+    /// Feeds `chunks` in order and flushes, as a stream reader does.
+    fn decode(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events: Vec<SseEvent> = chunks.iter().flat_map(|c| decoder.feed(c)).collect();
+        events.extend(decoder.finish());
+        events
+    }
+
+    const STREAM: &str = ": keep-alive\r\n\
+        data: {\"text\":\"çok güzel 🚀\"}\r\n\
+        \r\n\
+        event: error\n\
+        id: 7\n\
+        data: first line\n\
+        data:second line\n\
+        \n\
+        data: [DONE]\n\
+        \n\
+        data: trailing ünicode";
+
+    fn expected() -> Vec<SseEvent> {
+        vec![
+            event(None, None, "{\"text\":\"çok güzel 🚀\"}"),
+            event(Some("error"), Some("7"), "first line\nsecond line"),
+            event(None, None, "[DONE]"),
+            event(None, None, "trailing ünicode"),
+        ]
+    }
+
+    #[test]
+    fn decodes_whole_stream() {
+        assert_eq!(decode(&[STREAM.as_bytes()]), expected());
+    }
+
+    #[test]
+    fn decodes_stream_split_at_every_byte() {
+        let bytes = STREAM.as_bytes();
+        for split in 0..=bytes.len() {
+            assert_eq!(decode(&[&bytes[..split], &bytes[split..]]), expected(), "split at byte {split}");
+        }
+        let single_bytes: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(decode(&single_bytes), expected());
+    }
+
+    #[test]
+    fn decodes_multibyte_character_split_across_chunks() {
+        let bytes = "data: 🚀\n\n".as_bytes();
+        // The rocket is four bytes, starting at byte 6.
+        assert_eq!(decode(&[&bytes[..7], &bytes[7..9], &bytes[9..]]), vec![event(None, None, "🚀")]);
+    }
+
+    #[test]
+    fn handles_crlf_and_multiline_data() {
+        assert_eq!(decode(&[b"data: a\r\n\r\n"]), vec![event(None, None, "a")]);
+        assert_eq!(decode(&[b"data: a\r\ndata: b\r\n\r\n"]), vec![event(None, None, "a\nb")]);
+        assert_eq!(decode(&[b"data: a\r", b"\n\r", b"\n"]), vec![event(None, None, "a")]);
+    }
+
+    #[test]
+    fn flushes_trailing_event_without_blank_line() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.feed(b"data: {\"done\":true}").is_empty());
+        assert_eq!(decoder.finish(), Some(event(None, None, "{\"done\":true}")));
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn skips_events_without_data() {
+        assert!(decode(&[b"event: ping\n\n: comment\n\n"]).is_empty());
+    }
+}
@@ -0,0 +1,386 @@
+//! Declarative tool permissions from the `policy` section of `kovaclaw.json`.
+//!
+//! ```json
+//! "policy": {
+//!   "channels": {
+//!     "whatsapp": {
+//!       "default": "deny",
+//!       "tools": {
+//!         "read_file": { "default": "allow", "rules": [{ "path": "/etc/**", "action": "deny" }] },
+//!         "shell_exec": { "rules": [{ "prefix": "git status", "action": "allow" }] }
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! For a call, the first matching rule of the tool wins, then the tool default,
+//! then the channel default, then the policy default. With nothing configured,
+//! tools flagged `needs_approval` ask and the rest are allowed.
+
+use crate::tools::workspace::Workspace;
+use crate::tools::ToolCall;
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl PolicyAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PolicyAction::Allow => "allow",
+            PolicyAction::Deny => "deny",
+            PolicyAction::Ask => "ask",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub default: Option<PolicyAction>,
+    #[serde(default)]
+    pub channels: HashMap<String, ChannelConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ChannelConfig {
+    #[serde(default)]
+    pub default: Option<PolicyAction>,
+    #[serde(default)]
+    pub tools: HashMap<String, ToolRules>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ToolRules {
+    #[serde(default)]
+    pub default: Option<PolicyAction>,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+/// One argument rule. Set exactly one of `prefix`, `regex` or `path`.
+/// `prefix` and `regex` match the `command` argument, `path` is a glob over the
+/// resolved `path` argument; `arg` picks a different argument.
+#[derive(Debug, Deserialize)]
+pub struct RuleConfig {
+    pub action: PolicyAction,
+    #[serde(default)]
+    pub arg: Option<String>,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+enum Matcher {
+    Prefix(String),
+    Regex(Regex),
+    Path(GlobMatcher),
+}
+
+struct Rule {
+    action: PolicyAction,
+    arg: String,
+    matcher: Matcher,
+}
+
+/// Shell operators that chain or redirect commands. `prefix` and `regex` rules never
+/// allow a command containing one, so `ls` can't be stretched into `ls; rm -rf ~`.
+const SHELL_OPERATORS: &[&str] = &[";", "&", "|", "`", "$(", ">", "<", "\n"];
+
+impl Rule {
+    fn compile(config: &RuleConfig) -> Result<Self> {
+        let (matcher, default_arg) = match (&config.prefix, &config.regex, &config.path) {
+            (Some(p), None, None) => (Matcher::Prefix(p.clone()), "command"),
+            (None, Some(r), None) => (
+                Matcher::Regex(Regex::new(r).with_context(|| format!("invalid policy regex `{r}`"))?),
+                "command",
+            ),
+            (None, None, Some(g)) => (
+                Matcher::Path(Glob::new(g).with_context(|| format!("invalid policy glob `{g}`"))?.compile_matcher()),
+                "path",
+            ),
+            _ => anyhow::bail!("policy rule needs exactly one of `prefix`, `regex` or `path`"),
+        };
+        Ok(Self {
+            action: config.action,
+            arg: config.arg.clone().unwrap_or_else(|| default_arg.into()),
+            matcher,
+        })
+    }
+
+    fn matches(&self, args: &serde_json::Value, workspace: &Workspace) -> bool {
+        let Some(value) = args.get(&self.arg).and_then(|v| v.as_str()) else {
+            return false;
+        };
+        let chained = || SHELL_OPERATORS.iter().any(|op| value.contains(op));
+        match &self.matcher {
+            Matcher::Prefix(_) | Matcher::Regex(_) if self.action == PolicyAction::Allow && chained() => false,
+            Matcher::Prefix(prefix) => value.trim_start().starts_with(prefix.as_str()),
+            Matcher::Regex(re) => re.is_match(value),
+            Matcher::Path(glob) => rule_paths(value, workspace).iter().any(|path| glob.is_match(path)),
+        }
+    }
+}
+
+/// What `path` rules see of a path argument: the real path the tool opens (symlinks and
+/// relative paths resolved through the workspace), and that path relative to each root
+/// it lies in, so both `/srv/kova/config/**` and `config/**` catch `./config/kovaclaw.json`.
+/// Paths outside the workspace, which the tools refuse anyway, are only made absolute.
+fn rule_paths(value: &str, workspace: &Workspace) -> Vec<PathBuf> {
+    let real = workspace.resolve(value)
+        .unwrap_or_else(|_| normalize_path(&workspace.cwd().join(value)));
+    let relative = workspace.roots().iter()
+        .filter_map(|root| real.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    std::iter::once(real).chain(relative).collect()
+}
+
+struct CompiledTool {
+    default: Option<PolicyAction>,
+    rules: Vec<Rule>,
+}
+
+/// The policy of one channel, compiled and ready to check calls.
+#[derive(Default)]
+pub struct ChannelPolicy {
+    channel: String,
+    default: Option<PolicyAction>,
+    tools: HashMap<String, CompiledTool>,
+}
+
+impl PolicyConfig {
+    /// Compiles the rules that apply to `channel` (`cli`, `whatsapp`, ...).
+    pub fn channel(&self, channel: &str) -> Result<ChannelPolicy> {
+        let mut policy = ChannelPolicy {
+            channel: channel.into(),
+            default: self.default,
+            tools: HashMap::new(),
+        };
+        let Some(config) = self.channels.get(channel) else {
+            return Ok(policy);
+        };
+        policy.default = config.default.or(self.default);
+        for (name, tool) in &config.tools {
+            let rules = tool.rules.iter()
+                .map(Rule::compile)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("policy for {channel}/{name}"))?;
+            policy.tools.insert(name.clone(), CompiledTool { default: tool.default, rules });
+        }
+        Ok(policy)
+    }
+}
+
+impl ChannelPolicy {
+    /// A policy that denies every tool.
+    pub fn deny_all(channel: &str) -> Self {
+        Self {
+            channel: channel.into(),
+            default: Some(PolicyAction::Deny),
+            tools: HashMap::new(),
+        }
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// What calls to `tool` get when none of its argument rules match, and how many rules it has.
+    pub fn tool_default(&self, tool: &str, needs_approval: bool) -> (PolicyAction, usize) {
+        let compiled = self.tools.get(tool);
+        let action = compiled.and_then(|t| t.default)
+            .or(self.default)
+            .unwrap_or(if needs_approval { PolicyAction::Ask } else { PolicyAction::Allow });
+        (action, compiled.map_or(0, |t| t.rules.len()))
+    }
+
+    /// Decides a call. `needs_approval` is the tool's own flag, used when nothing is configured;
+    /// `workspace` resolves path arguments the way the file tools will.
+    pub fn decide(&self, call: &ToolCall, needs_approval: bool, workspace: &Workspace) -> PolicyAction {
+        let tool = self.tools.get(&call.name);
+        tool.and_then(|t| t.rules.iter().find(|r| r.matches(&call.arguments, workspace)))
+            .map(|r| match r.action {
+                // Rules vet the command, not the environment it runs in: `GIT_CONFIG_*` or
+                // `LD_PRELOAD` turn an allowed `git status` into any program.
+                PolicyAction::Allow if sets_env(&call.arguments) => PolicyAction::Ask,
+                action => action,
+            })
+            .or_else(|| tool.and_then(|t| t.default))
+            .or(self.default)
+            .unwrap_or(if needs_approval { PolicyAction::Ask } else { PolicyAction::Allow })
+    }
+}
+
+fn sets_env(args: &serde_json::Value) -> bool {
+    args.get("env").and_then(|e| e.as_object()).is_some_and(|env| !env.is_empty())
+}
+
+/// Resolves `.` and `..` without touching the filesystem.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() && !path.is_absolute() {
+                    out.push("..");
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use serde_json::json;
+
+    fn policy(config: serde_json::Value) -> ChannelPolicy {
+        let config: PolicyConfig = serde_json::from_value(json!({ "channels": { "test": config } })).unwrap();
+        config.channel("test").unwrap()
+    }
+
+    fn call(name: &str, arguments: serde_json::Value) -> ToolCall {
+        ToolCall { id: String::new(), name: name.into(), arguments }
+    }
+
+    fn decide(policy: &ChannelPolicy, call: &ToolCall) -> PolicyAction {
+        policy.decide(call, true, &Workspace::deny_all())
+    }
+
+    fn shell_policy() -> ChannelPolicy {
+        policy(json!({ "tools": { "shell_exec": { "default": "deny", "rules": [
+            { "regex": "\\bsudo\\b", "action": "deny" },
+            { "prefix": "ls", "action": "allow" },
+            { "regex": "^git (status|log|diff)\\b", "action": "allow" },
+        ] } } }))
+    }
+
+    #[test]
+    fn allow_rules_match_plain_commands_only() {
+        let policy = shell_policy();
+        let command = |command: &str| decide(&policy, &call("shell_exec", json!({ "command": command })));
+        assert_eq!(command("git status"), PolicyAction::Allow);
+        assert_eq!(command("ls -la"), PolicyAction::Allow);
+        assert_eq!(command("ls; rm -rf ~"), PolicyAction::Deny);
+        assert_eq!(command("git status && curl evil"), PolicyAction::Deny);
+        assert_eq!(command("sudo ls"), PolicyAction::Deny);
+    }
+
+    #[test]
+    fn allow_rules_ask_when_env_is_set() {
+        let policy = shell_policy();
+        let fsmonitor = call("shell_exec", json!({
+            "command": "git status",
+            "env": {
+                "GIT_CONFIG_COUNT": "1",
+                "GIT_CONFIG_KEY_0": "core.fsmonitor",
+                "GIT_CONFIG_VALUE_0": "touch /tmp/pwned; false",
+            },
+        }));
+        assert_eq!(decide(&policy, &fsmonitor), PolicyAction::Ask);
+        let preload = call("shell_exec", json!({ "command": "ls", "env": { "LD_PRELOAD": "/tmp/evil.so" } }));
+        assert_eq!(decide(&policy, &preload), PolicyAction::Ask);
+        let empty = call("shell_exec", json!({ "command": "ls", "env": {} }));
+        assert_eq!(decide(&policy, &empty), PolicyAction::Allow);
+        let denied = call("shell_exec", json!({ "command": "sudo ls", "env": { "A": "1" } }));
+        assert_eq!(decide(&policy, &denied), PolicyAction::Deny);
+    }
+
+    fn read_policy(root: &Path) -> ChannelPolicy {
+        policy(json!({ "tools": { "read_file": { "default": "allow", "rules": [
+            { "path": format!("{}/config/**", root.display()), "action": "deny" },
+            { "path": "sessions/**", "action": "deny" },
+            { "path": "**/auth_state/**", "action": "deny" },
+        ] } } }))
+    }
+
+    #[test]
+    fn path_rules_see_relative_paths_resolved() {
+        let root = TempDir::new();
+        root.write("config/kovaclaw.json", "{}");
+        root.write("sessions/a.jsonl", "");
+        root.write("notes/todo.md", "");
+        let workspace = Workspace::new(&[root.path().to_path_buf()], Some(&root.path().join("notes"))).unwrap();
+        let policy = read_policy(root.path());
+        let read = |path: &str| policy.decide(&call("read_file", json!({ "path": path })), false, &workspace);
+
+        assert_eq!(read(&root.path().join("config/kovaclaw.json").display().to_string()), PolicyAction::Deny);
+        assert_eq!(read("../config/kovaclaw.json"), PolicyAction::Deny);
+        assert_eq!(read("./../notes/../config/kovaclaw.json"), PolicyAction::Deny);
+        assert_eq!(read("../sessions/a.jsonl"), PolicyAction::Deny);
+        assert_eq!(read(&root.path().join("sessions/a.jsonl").display().to_string()), PolicyAction::Deny);
+        assert_eq!(read("todo.md"), PolicyAction::Allow);
+    }
+
+    #[test]
+    fn path_rules_see_through_symlinks() {
+        let root = TempDir::new();
+        root.write("config/kovaclaw.json", "{}");
+        root.write("bridge/auth_state/creds.json", "{}");
+        std::os::unix::fs::symlink(root.path().join("config"), root.path().join("settings")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("bridge/auth_state"), root.path().join("keys")).unwrap();
+        let workspace = Workspace::new(&[root.path().to_path_buf()], None).unwrap();
+        let policy = read_policy(root.path());
+        let read = |path: &str| policy.decide(&call("read_file", json!({ "path": path })), false, &workspace);
+
+        assert_eq!(read("settings/kovaclaw.json"), PolicyAction::Deny);
+        assert_eq!(read("keys/creds.json"), PolicyAction::Deny);
+        assert_eq!(read("settings/new.json"), PolicyAction::Deny);
+    }
+
+    #[test]
+    fn shipped_whatsapp_policy_keeps_secrets_and_chats_in() {
+        let config: serde_json::Value = serde_json::from_str(include_str!("../../../config/kovaclaw.json")).unwrap();
+        let config: PolicyConfig = serde_json::from_value(config["policy"].clone()).unwrap();
+        let policy = config.channel("whatsapp").unwrap();
+        let root = TempDir::new();
+        root.write("config/kovaclaw.json", "{}");
+        root.write("sessions/whatsapp/905550000000@s.whatsapp.net.jsonl", "");
+        root.write("media/3EB0F1E2D3C4B5A69788.pdf", "");
+        root.write("notes/todo.md", "");
+        let workspace = Workspace::new(&[root.path().to_path_buf()], None).unwrap();
+
+        for tool in ["read_file", "send_file"] {
+            let decide = |path: String| policy.decide(&call(tool, json!({ "path": path })), false, &workspace);
+            assert_eq!(decide("config/kovaclaw.json".into()), PolicyAction::Deny, "{tool}");
+            assert_eq!(decide("./notes/../config/kovaclaw.json".into()), PolicyAction::Deny, "{tool}");
+            assert_eq!(decide(root.path().join("config/kovaclaw.json").display().to_string()), PolicyAction::Deny, "{tool}");
+            assert_eq!(decide("sessions/whatsapp/905550000000@s.whatsapp.net.jsonl".into()), PolicyAction::Deny, "{tool}");
+            // Every chat's attachments; documents reach the agent inline instead.
+            assert_eq!(decide("media/3EB0F1E2D3C4B5A69788.pdf".into()), PolicyAction::Deny, "{tool}");
+            assert_eq!(decide(root.path().join("media/3EB0F1E2D3C4B5A69788.pdf").display().to_string()), PolicyAction::Deny, "{tool}");
+            assert_eq!(decide("notes/todo.md".into()), PolicyAction::Allow, "{tool}");
+        }
+
+        let shell = |command: &str| policy.decide(&call("shell_exec", json!({ "command": command })), true, &workspace);
+        assert_eq!(shell("git status"), PolicyAction::Allow);
+        assert_eq!(shell("ls -la notes"), PolicyAction::Allow);
+        assert_eq!(shell("df -h"), PolicyAction::Allow);
+        // Writes `.git/config`, after which `git status` runs the fsmonitor command.
+        assert_eq!(shell("git log -1 --format='[core]%n%x09fsmonitor = touch pwned' --output=.git/config"), PolicyAction::Deny);
+        assert_eq!(shell("git diff --ext-diff"), PolicyAction::Deny);
+        assert_eq!(shell("git -c core.fsmonitor=evil status"), PolicyAction::Deny);
+        assert_eq!(shell("lsof"), PolicyAction::Deny);
+        assert_eq!(shell("lsblk"), PolicyAction::Deny);
+        assert_eq!(shell("dfx"), PolicyAction::Deny);
+    }
+}
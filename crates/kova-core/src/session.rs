@@ -1,4 +1,5 @@
-use crate::event::Message;
+use crate::context::SUMMARY_HEADER;
+use crate::event::{Message, Role};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs::{self, OpenOptions};
@@ -8,13 +9,51 @@ use std::path::{Path, PathBuf};
 #[derive(Serialize, Deserialize)]
 struct SessionEntry {
     timestamp: chrono::DateTime<chrono::Utc>,
-    message: Message,
+    #[serde(flatten)]
+    record: Record,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Message(Message),
+    Compaction(Compaction),
+    /// The history was cut back to its first `len` messages (`/reset`, `/undo`).
+    Rewind { len: usize },
+}
+
+/// The first `replaced` messages of the history were folded into `summary`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compaction {
+    pub summary: String,
+    pub replaced: usize,
+}
+
+impl Compaction {
+    /// Applies the compaction to a history, leaving the summary as its first message. The
+    /// agent sends it as part of the system prompt.
+    pub fn apply(&self, history: &mut Vec<Message>) {
+        let replaced = self.replaced.min(history.len());
+        history.splice(..replaced, [Message::new(Role::System, format!("{SUMMARY_HEADER}\n{}", self.summary))]);
+    }
 }
 
 pub struct Session {
     path: PathBuf,
 }
 
+/// A session file found by `Session::list`.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    pub modified: chrono::DateTime<chrono::Local>,
+    /// Messages the session resumes with: rewound ones are gone, compacted ones count
+    /// as their summary.
+    pub messages: usize,
+    /// First line of the first user message, to recognise the conversation by.
+    pub first_line: String,
+}
+
 impl Session {
     pub fn new(session_dir: &Path, id: &str) -> Result<Self> {
         fs::create_dir_all(session_dir)?;
@@ -23,10 +62,79 @@ impl Session {
         })
     }
 
+    /// Sessions in `session_dir`, most recently used first. Subdirectories (such as
+    /// `whatsapp/`) are not searched.
+    pub fn list(session_dir: &Path) -> Result<Vec<SessionInfo>> {
+        let mut sessions = Vec::new();
+        let entries = match fs::read_dir(session_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(sessions),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "jsonl") || !path.is_file() {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let content = fs::read_to_string(&path)?;
+            let first_line = content.lines()
+                .filter_map(|line| serde_json::from_str::<SessionEntry>(line).ok())
+                .find_map(|entry| match entry.record {
+                    Record::Message(message) if matches!(message.role, Role::User) => {
+                        Some(message.content.lines().next().unwrap_or_default().to_string())
+                    }
+                    _ => None,
+                });
+            sessions.push(SessionInfo {
+                id: id.to_string(),
+                modified: fs::metadata(&path)?.modified()?.into(),
+                messages: replay(&path, &content).len(),
+                first_line: first_line.unwrap_or_default(),
+            });
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.modified));
+        Ok(sessions)
+    }
+
+    pub fn id(&self) -> &str {
+        self.path.file_stem().and_then(|s| s.to_str()).unwrap_or_default()
+    }
+
+    /// Starts session `id` next to this one with a copy of its history.
+    pub fn fork(&self, id: &str) -> Result<Session> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let fork = Session::new(dir, id)?;
+        if fork.exists() {
+            anyhow::bail!("session {id} already exists");
+        }
+        fs::copy(&self.path, &fork.path)?;
+        Ok(fork)
+    }
+
+    /// Whether anything was written to this session yet.
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
     pub fn append(&self, message: &Message) -> Result<()> {
+        self.write(Record::Message(message.clone()))
+    }
+
+    pub fn append_compaction(&self, compaction: &Compaction) -> Result<()> {
+        self.write(Record::Compaction(compaction.clone()))
+    }
+
+    pub fn append_rewind(&self, len: usize) -> Result<()> {
+        self.write(Record::Rewind { len })
+    }
+
+    fn write(&self, record: Record) -> Result<()> {
         let entry = SessionEntry {
             timestamp: chrono::Utc::now(),
-            message: message.clone(),
+            record,
         };
         let mut file = OpenOptions::new()
             .create(true)
@@ -37,17 +145,59 @@ impl Session {
         Ok(())
     }
 
+    /// Replays the file into the history the agent had, compactions included.
     pub fn load(&self) -> Result<Vec<Message>> {
         if !self.path.exists() {
             return Ok(Vec::new());
         }
         let content = fs::read_to_string(&self.path)?;
-        let mut messages = Vec::new();
-        for line in content.lines() {
-            if let Ok(entry) = serde_json::from_str::<SessionEntry>(line) {
-                messages.push(entry.message);
+        Ok(replay(&self.path, &content))
+    }
+}
+
+/// The history recorded in `content`, the session file at `path`.
+fn replay(path: &Path, content: &str) -> Vec<Message> {
+    let mut messages = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SessionEntry>(line) {
+            Ok(SessionEntry { record: Record::Message(message), .. }) => messages.push(message),
+            Ok(SessionEntry { record: Record::Compaction(compaction), .. }) => {
+                compaction.apply(&mut messages);
             }
+            Ok(SessionEntry { record: Record::Rewind { len }, .. }) => messages.truncate(len),
+            Err(e) => tracing::warn!("{}:{}: skipping bad session entry: {e}", path.display(), i + 1),
         }
-        Ok(messages)
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn list_counts_the_history_a_resume_gets() {
+        let dir = TempDir::new();
+        let session = Session::new(dir.path(), "s1").unwrap();
+        for i in 0..3 {
+            session.append(&Message::new(Role::User, format!("question {i}\nmore"))).unwrap();
+            session.append(&Message::new(Role::Assistant, format!("answer {i}"))).unwrap();
+        }
+        // `/undo` of the last exchange, then a compaction of the first two messages.
+        session.append_rewind(4).unwrap();
+        session.append_compaction(&Compaction { summary: "asked twice".into(), replaced: 2 }).unwrap();
+        session.append(&Message::new(Role::User, "question 3")).unwrap();
+
+        let history = session.load().unwrap();
+        assert_eq!(history.len(), 4);
+        let sessions = Session::list(dir.path()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "s1");
+        assert_eq!(sessions[0].messages, history.len());
+        assert_eq!(sessions[0].first_line, "question 0");
     }
 }
@@ -0,0 +1,169 @@
+//! Helpers shared by the unit tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A fresh directory under the system temp dir, removed again on drop.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("kova-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Canonical, so paths the workspace hands back compare equal.
+        Self(dir.canonicalize().unwrap())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `content` to `relative`, creating parent directories.
+    pub fn write(&self, relative: &str, content: &str) -> PathBuf {
+        let path = self.0.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A canned HTTP response of `MockServer`.
+pub struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    /// Written one by one, flushed and a moment apart, so the client reads them separately.
+    chunks: Vec<Vec<u8>>,
+}
+
+impl MockResponse {
+    pub fn json(body: serde_json::Value) -> Self {
+        Self { status: 200, content_type: "application/json", chunks: vec![body.to_string().into_bytes()] }
+    }
+
+    /// `body` cut into pieces of `size` bytes, regardless of lines or characters.
+    pub fn stream(content_type: &'static str, body: &str, size: usize) -> Self {
+        let chunks = body.as_bytes().chunks(size).map(<[u8]>::to_vec).collect();
+        Self { status: 200, content_type, chunks }
+    }
+
+    pub fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// An HTTP server on localhost answering requests with `MockResponse`s in order and
+/// recording the requests it received.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+struct MockRequest {
+    /// Lowercased names, values as sent.
+    headers: Vec<(String, String)>,
+    body: serde_json::Value,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<MockResponse>) -> Self {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<Vec<MockRequest>>> = Arc::default();
+        let recorded = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                socket.set_nodelay(true).unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let body_start = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                };
+                let headers: Vec<(String, String)> = String::from_utf8_lossy(&request[..body_start])
+                    .lines()
+                    .filter_map(|l| l.split_once(':'))
+                    .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+                    .collect();
+                let length: usize = headers.iter()
+                    .find(|(name, _)| name == "content-length")
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                while request.len() < body_start + length {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let body = serde_json::from_slice(&request[body_start..]).unwrap_or(serde_json::Value::Null);
+                recorded.lock().unwrap().push(MockRequest { headers, body });
+
+                let head = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: {}\r\ntransfer-encoding: chunked\r\nconnection: close\r\n\r\n",
+                    response.status, response.content_type,
+                );
+                socket.write_all(head.as_bytes()).await.unwrap();
+                for chunk in response.chunks {
+                    socket.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await.unwrap();
+                    socket.write_all(&chunk).await.unwrap();
+                    socket.write_all(b"\r\n").await.unwrap();
+                    socket.flush().await.unwrap();
+                    tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+                }
+                socket.write_all(b"0\r\n\r\n").await.unwrap();
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// JSON bodies of the requests so far.
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.requests.lock().unwrap().iter().map(|r| r.body.clone()).collect()
+    }
+
+    /// Header `name` of request `index`.
+    pub fn header(&self, index: usize, name: &str) -> Option<String> {
+        let requests = self.requests.lock().unwrap();
+        requests[index].headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone())
+    }
+}
+
+/// Backend settings pointing at `base_url`, defaults otherwise.
+pub fn llm_config(base_url: &str, provider: &str) -> crate::config::LlmConfig {
+    serde_json::from_value(serde_json::json!({ "base_url": base_url, "provider": provider, "model": "test-model" })).unwrap()
+}
+
+/// A system prompt, a question, an assistant turn with two tool calls, their results and a
+/// follow-up, plus the definition of one tool: what every backend has to put on the wire.
+pub fn tool_conversation() -> (Vec<crate::event::Message>, Vec<crate::tools::ToolDef>) {
+    use crate::event::{Message, Role};
+    use crate::tools::{ToolCall, ToolDef};
+
+    let calls = [
+        ToolCall { id: "call_1".into(), name: "read_file".into(), arguments: serde_json::json!({ "path": "a.txt" }) },
+        ToolCall { id: "call_2".into(), name: "shell_exec".into(), arguments: serde_json::json!({ "command": "wc -l a.txt" }) },
+    ];
+    let messages = vec![
+        Message::new(Role::System, "You are kova."),
+        Message::new(Role::User, "What is in a.txt?"),
+        Message::assistant_with_calls("Let me look.", calls.to_vec()),
+        Message::tool_result(&calls[0], "hello"),
+        Message::tool_result(&calls[1], "1 a.txt"),
+        Message::new(Role::User, "And b.txt?"),
+    ];
+    let tools = vec![ToolDef {
+        name: "read_file".into(),
+        description: "Read a file".into(),
+        parameters: serde_json::json!({ "type": "object", "properties": { "path": { "type": "string" } } }),
+    }];
+    (messages, tools)
+}
@@ -0,0 +1,249 @@
+//! UTF-8 safe helpers for cutting text to size. Lengths are counted in characters,
+//! so a cut never lands inside a multibyte character.
+
+/// Room left in each part for the `(i/n) ` marker.
+const MARKER_RESERVE: usize = 10;
+
+/// Shortens `text` to at most `max_chars` characters, marking the cut with `...`.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Splits a message into parts of at most `max_chars` characters. Multi-part
+/// messages are numbered `(1/3) `, `(2/3) `, ...
+pub fn split_message(text: &str, max_chars: usize) -> Vec<String> {
+    let text = text.trim();
+    if char_len(text) <= max_chars {
+        return vec![text.to_string()];
+    }
+    let parts = chunk(text, max_chars.saturating_sub(MARKER_RESERVE).max(1));
+    let total = parts.len();
+    parts.into_iter()
+        .enumerate()
+        .map(|(i, part)| format!("({}/{total}) {part}", i + 1))
+        .collect()
+}
+
+/// Splits `text` into chunks of at most `max_chars` characters. Breaks fall between
+/// paragraphs where possible, then between sentences, then between words. Code
+/// fences are split between lines and reopened in the next chunk.
+pub fn chunk(text: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for block in blocks(text) {
+        let pieces = if char_len(&block) <= max_chars {
+            vec![block]
+        } else if block.starts_with("```") {
+            split_fence(&block, max_chars)
+        } else {
+            pack(sentences(&block), max_chars, |s| {
+                pack(s.split_inclusive(char::is_whitespace), max_chars, |w| hard_split(w, max_chars))
+            })
+        };
+        for piece in pieces {
+            if !current.is_empty() && char_len(&current) + 2 + char_len(&piece) > max_chars {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(&piece);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Paragraphs separated by blank lines; a code fence is one block even with blank lines inside.
+fn blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    let flush = |current: &mut Vec<&str>, blocks: &mut Vec<String>| {
+        if !current.is_empty() {
+            blocks.push(current.join("\n").trim_end().to_string());
+            current.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let fence = line.trim_start().starts_with("```");
+        if in_fence {
+            current.push(line);
+            if fence {
+                in_fence = false;
+                flush(&mut current, &mut blocks);
+            }
+        } else if fence {
+            flush(&mut current, &mut blocks);
+            current.push(line);
+            in_fence = true;
+        } else if line.trim().is_empty() {
+            flush(&mut current, &mut blocks);
+        } else {
+            current.push(line);
+        }
+    }
+    flush(&mut current, &mut blocks);
+    blocks
+}
+
+/// Sentences and lines, each with its trailing whitespace.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let boundary = c == '\n'
+            || (matches!(c, '.' | '!' | '?' | '…') && chars.peek().is_some_and(|(_, next)| next.is_whitespace()));
+        if !boundary {
+            continue;
+        }
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, next)) = chars.peek() {
+            if !next.is_whitespace() {
+                break;
+            }
+            end = j + next.len_utf8();
+            chars.next();
+        }
+        out.push(&text[start..end]);
+        start = end;
+    }
+    if start < text.len() {
+        out.push(&text[start..]);
+    }
+    out
+}
+
+/// Greedily joins pieces into chunks of at most `max_chars`; pieces that are too long
+/// on their own go through `too_long`.
+fn pack<'a>(
+    pieces: impl IntoIterator<Item = &'a str>,
+    max_chars: usize,
+    too_long: impl Fn(&str) -> Vec<String>,
+) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let flush = |current: &mut String, out: &mut Vec<String>| {
+        let text = current.trim_end();
+        if !text.is_empty() {
+            out.push(text.to_string());
+        }
+        current.clear();
+    };
+
+    for piece in pieces {
+        let len = char_len(piece.trim_end());
+        if len > max_chars {
+            flush(&mut current, &mut out);
+            out.extend(too_long(piece.trim_end()));
+            continue;
+        }
+        if char_len(&current) + len > max_chars {
+            flush(&mut current, &mut out);
+        }
+        current.push_str(piece);
+    }
+    flush(&mut current, &mut out);
+    out
+}
+
+fn hard_split(text: &str, max_chars: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    chars.chunks(max_chars.max(1)).map(|c| c.iter().collect()).collect()
+}
+
+/// Splits a fenced code block between lines, repeating the opening fence in every part.
+fn split_fence(block: &str, max_chars: usize) -> Vec<String> {
+    let (header, body) = block.split_once('\n').unwrap_or((block, ""));
+    let body = body.trim_end();
+    let body = body.strip_suffix("```").unwrap_or(body).trim_end_matches('\n');
+    let room = max_chars.saturating_sub(char_len(header) + 5).max(1);
+    pack(body.split_inclusive('\n'), room, |line| hard_split(line, room))
+        .into_iter()
+        .map(|part| format!("{header}\n{part}\n```"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_cuts_turkish_and_emoji_on_character_boundaries() {
+        assert_eq!(truncate("çağrı", 3), "çağ...");
+        assert_eq!(truncate("👋🏽🚀ş", 2), "👋🏽...");
+        assert_eq!(truncate("ığüşöç", 6), "ığüşöç");
+    }
+
+    #[test]
+    fn chunk_splits_multibyte_text_at_the_limit() {
+        let text = "ğüşıöç 🚀🚀🚀 çalışıyor";
+        for max in 1..=char_len(text) {
+            let chunks = chunk(text, max);
+            assert!(chunks.iter().all(|c| char_len(c) <= max), "{max}: {chunks:?}");
+            assert_eq!(chunks.concat().replace(char::is_whitespace, ""), text.replace(' ', ""));
+        }
+    }
+
+    #[test]
+    fn chunk_prefers_paragraphs_then_sentences_then_words() {
+        let text = "Birinci paragraf.\n\nİkinci cümle burada. Üçüncü cümle de burada.";
+        assert_eq!(chunk(text, 30), ["Birinci paragraf.", "İkinci cümle burada.", "Üçüncü cümle de burada."]);
+        assert_eq!(chunk("bir iki üç dört", 8), ["bir iki", "üç dört"]);
+    }
+
+    #[test]
+    fn chunk_hard_splits_an_oversized_word_and_sentence() {
+        let word = "ş".repeat(25);
+        assert_eq!(chunk(&word, 10), ["ş".repeat(10), "ş".repeat(10), "ş".repeat(5)]);
+
+        let sentence = "kelime ".repeat(10);
+        let chunks = chunk(sentence.trim(), 20);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| char_len(c) <= 20 && !c.contains("kelimekelime")), "{chunks:?}");
+    }
+
+    #[test]
+    fn oversized_fence_is_reopened_in_every_part() {
+        let lines: Vec<String> = (0..20).map(|i| format!("let x{i} = \"ç\";")).collect();
+        let block = format!("```rust\n{}\n```", lines.join("\n"));
+        let parts = chunk(&format!("Kod:\n\n{block}"), 60);
+        let fenced: Vec<_> = parts.iter().filter(|p| p.contains("let x")).collect();
+        assert!(fenced.len() > 1);
+        for part in &parts {
+            assert!(char_len(part) <= 60, "{part}");
+        }
+        for part in fenced {
+            assert!(part.contains("```rust\n") && part.ends_with("\n```"), "{part}");
+        }
+        assert_eq!(split_fence(&block, 60).concat().matches("let x").count(), 20);
+    }
+
+    #[test]
+    fn numbered_parts_stay_within_the_limit() {
+        const MAX: usize = 4000;
+        let text = "Çok uzun bir cümle 🚀 burada duruyor. ".repeat(400);
+        let parts = split_message(&text, MAX);
+        let total = parts.len();
+        assert!(total > 1);
+        for (i, part) in parts.iter().enumerate() {
+            assert!(part.starts_with(&format!("({}/{total}) ", i + 1)), "{part}");
+            assert!(char_len(part) <= MAX);
+        }
+
+        let word = "ğ".repeat(MAX * 3);
+        assert!(split_message(&word, MAX).iter().all(|p| char_len(p) <= MAX));
+        assert_eq!(split_message("  kısa mesaj  ", MAX), ["kısa mesaj"]);
+    }
+}
@@ -1,19 +1,20 @@
-use super::{Tool, ToolDef, ToolOutput};
+use super::{SyncTool, ToolContext, ToolDef, ToolOutput};
 use anyhow::Result;
 use serde_json::json;
+use std::path::Path;
 
 pub struct ReadFile;
 pub struct WriteFile;
 
-impl Tool for ReadFile {
+impl SyncTool for ReadFile {
     fn definition(&self) -> ToolDef {
         ToolDef {
             name: "read_file".into(),
-            description: "Read the contents of a file".into(),
+            description: "Read the contents of a text file; PDFs are returned as their extracted text".into(),
             parameters: json!({
                 "type": "object",
                 "properties": {
-                    "path": { "type": "string", "description": "File path to read" }
+                    "path": { "type": "string", "description": "File path to read, absolute or relative to the working directory" }
                 },
                 "required": ["path"]
             }),
@@ -22,17 +23,31 @@ impl Tool for ReadFile {
 
     fn needs_approval(&self) -> bool { false }
 
-    fn execute(&self, args: serde_json::Value) -> Result<ToolOutput> {
+    fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
         let path = args["path"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing 'path' argument"))?;
-        match std::fs::read_to_string(path) {
+        let path = match ctx.workspace.resolve(path) {
+            Ok(p) => p,
+            Err(e) => return Ok(ToolOutput::error(e)),
+        };
+        match read_text(&path) {
             Ok(content) => Ok(ToolOutput { success: true, output: content }),
             Err(e) => Ok(ToolOutput { success: false, output: format!("Error: {e}") }),
         }
     }
 }
 
-impl Tool for WriteFile {
+/// The text of a file: PDFs are extracted, anything else must be UTF-8.
+pub fn read_text(path: &Path) -> Result<String, String> {
+    let is_pdf = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+    if is_pdf {
+        pdf_extract::extract_text(path).map_err(|e| e.to_string())
+    } else {
+        std::fs::read_to_string(path).map_err(|e| e.to_string())
+    }
+}
+
+impl SyncTool for WriteFile {
     fn definition(&self) -> ToolDef {
         ToolDef {
             name: "write_file".into(),
@@ -40,7 +55,7 @@ impl Tool for WriteFile {
             parameters: json!({
                 "type": "object",
                 "properties": {
-                    "path": { "type": "string", "description": "File path to write" },
+                    "path": { "type": "string", "description": "File path to write, absolute or relative to the working directory" },
                     "content": { "type": "string", "description": "Content to write" }
                 },
                 "required": ["path", "content"]
@@ -48,17 +63,21 @@ impl Tool for WriteFile {
         }
     }
 
-    fn execute(&self, args: serde_json::Value) -> Result<ToolOutput> {
+    fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
         let path = args["path"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing 'path' argument"))?;
         let content = args["content"].as_str()
             .ok_or_else(|| anyhow::anyhow!("Missing 'content' argument"))?;
+        let path = match ctx.workspace.resolve(path) {
+            Ok(p) => p,
+            Err(e) => return Ok(ToolOutput::error(e)),
+        };
 
-        if let Some(parent) = std::path::Path::new(path).parent() {
+        if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        match std::fs::write(path, content) {
-            Ok(()) => Ok(ToolOutput { success: true, output: format!("Written to {path}") }),
+        match std::fs::write(&path, content) {
+            Ok(()) => Ok(ToolOutput { success: true, output: format!("Written to {}", path.display()) }),
             Err(e) => Ok(ToolOutput { success: false, output: format!("Error: {e}") }),
         }
     }
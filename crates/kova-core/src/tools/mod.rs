@@ -1,9 +1,16 @@
 pub mod fs;
 pub mod shell;
+pub mod workspace;
 
+use crate::policy::{ChannelPolicy, PolicyAction};
 use anyhow::Result;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use workspace::Workspace;
+
+const DEFAULT_CONCURRENCY: usize = 4;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolDef {
@@ -14,6 +21,9 @@ pub struct ToolDef {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
+    /// Call id assigned by the model. Empty for calls scraped from plain text.
+    #[serde(default)]
+    pub id: String,
     pub name: String,
     pub arguments: serde_json::Value,
 }
@@ -24,19 +34,88 @@ pub struct ToolOutput {
     pub output: String,
 }
 
+impl ToolOutput {
+    pub fn error(message: impl std::fmt::Display) -> Self {
+        Self { success: false, output: format!("Error: {message}") }
+    }
+}
+
+/// Per-session state handed to every tool call.
+#[derive(Clone)]
+pub struct ToolContext {
+    pub workspace: Workspace,
+}
+
+impl Default for ToolContext {
+    fn default() -> Self {
+        Self {
+            workspace: Workspace::current_dir().unwrap_or_else(|_| Workspace::deny_all()),
+        }
+    }
+}
+
+#[async_trait]
 pub trait Tool: Send + Sync {
     fn definition(&self) -> ToolDef;
-    fn execute(&self, args: serde_json::Value) -> Result<ToolOutput>;
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput>;
     fn needs_approval(&self) -> bool { true }
 }
 
+/// A tool with blocking `execute`. Register it with `ToolRegistry::register_sync`,
+/// which runs it on the blocking thread pool.
+pub trait SyncTool: Send + Sync + 'static {
+    fn definition(&self) -> ToolDef;
+    fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput>;
+    fn needs_approval(&self) -> bool { true }
+}
+
+/// Adapts a `SyncTool` to the async `Tool` trait.
+pub struct Blocking<T>(Arc<T>);
+
+impl<T: SyncTool> Blocking<T> {
+    pub fn new(tool: T) -> Self {
+        Self(Arc::new(tool))
+    }
+}
+
+#[async_trait]
+impl<T: SyncTool> Tool for Blocking<T> {
+    fn definition(&self) -> ToolDef {
+        self.0.definition()
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
+        let tool = self.0.clone();
+        let ctx = ctx.clone();
+        tokio::task::spawn_blocking(move || tool.execute(args, &ctx)).await?
+    }
+
+    fn needs_approval(&self) -> bool {
+        self.0.needs_approval()
+    }
+}
+
 pub struct ToolRegistry {
     tools: HashMap<String, Box<dyn Tool>>,
+    policy: ChannelPolicy,
+    context: ToolContext,
+    concurrency: usize,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ToolRegistry {
     pub fn new() -> Self {
-        Self { tools: HashMap::new() }
+        Self {
+            tools: HashMap::new(),
+            policy: ChannelPolicy::default(),
+            context: ToolContext::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
     }
 
     pub fn register(&mut self, tool: Box<dyn Tool>) {
@@ -44,10 +123,14 @@ impl ToolRegistry {
         self.tools.insert(name, tool);
     }
 
+    pub fn register_sync<T: SyncTool>(&mut self, tool: T) {
+        self.register(Box::new(Blocking::new(tool)));
+    }
+
     pub fn register_defaults(&mut self) {
-        self.register(Box::new(fs::ReadFile));
-        self.register(Box::new(fs::WriteFile));
-        self.register(Box::new(shell::ShellExec));
+        self.register_sync(fs::ReadFile);
+        self.register_sync(fs::WriteFile);
+        self.register(Box::new(shell::ShellExec::default()));
     }
 
     pub fn definitions(&self) -> Vec<ToolDef> {
@@ -58,9 +141,197 @@ impl ToolRegistry {
         self.tools.get(name).map(|t| t.as_ref())
     }
 
-    pub fn execute(&self, call: &ToolCall) -> Result<ToolOutput> {
+    pub fn set_policy(&mut self, policy: ChannelPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn policy(&self) -> &ChannelPolicy {
+        &self.policy
+    }
+
+    /// How many calls of one round `execute_all` runs at once.
+    pub fn set_concurrency(&mut self, limit: usize) {
+        self.concurrency = limit.max(1);
+    }
+
+    pub fn set_workspace(&mut self, workspace: Workspace) {
+        self.context.workspace = workspace;
+    }
+
+    pub fn workspace(&self) -> &Workspace {
+        &self.context.workspace
+    }
+
+    pub fn workspace_mut(&mut self) -> &mut Workspace {
+        &mut self.context.workspace
+    }
+
+    /// What the channel policy says about this call.
+    pub fn decide(&self, call: &ToolCall) -> PolicyAction {
+        let needs_approval = self.get(&call.name).is_none_or(|t| t.needs_approval());
+        self.policy.decide(call, needs_approval, &self.context.workspace)
+    }
+
+    /// Runs a call if the policy permits it. `approved` says a human (or approver)
+    /// said yes; it unlocks `ask` calls but never `deny` ones.
+    pub async fn execute(&self, call: &ToolCall, approved: bool) -> Result<ToolOutput> {
         let tool = self.tools.get(&call.name)
             .ok_or_else(|| anyhow::anyhow!("Unknown tool: {}", call.name))?;
-        tool.execute(call.arguments.clone())
+        let denied = match self.decide(call) {
+            PolicyAction::Allow => None,
+            PolicyAction::Ask if approved => None,
+            PolicyAction::Ask => Some("needs approval"),
+            PolicyAction::Deny => Some("denied"),
+        };
+        if let Some(why) = denied {
+            tracing::warn!(channel = self.policy.channel(), tool = %call.name, args = %call.arguments, "tool call {why} by policy");
+            return Ok(ToolOutput {
+                success: false,
+                output: format!("Error: {} {why} by policy", call.name),
+            });
+        }
+        tool.execute(call.arguments.clone(), &self.context).await
+    }
+
+    /// Runs independent calls concurrently, at most `concurrency` at a time.
+    /// Results come back in the order of `calls`.
+    pub async fn execute_all(&self, calls: &[(ToolCall, bool)]) -> Vec<Result<ToolOutput>> {
+        let permits = tokio::sync::Semaphore::new(self.concurrency);
+        // A plain loop rather than `map`: a closure returning a borrowing future
+        // makes the agent's stream fail the `Send` check.
+        let mut runs = Vec::with_capacity(calls.len());
+        for (call, approved) in calls {
+            runs.push(self.execute_limited(&permits, call, *approved));
+        }
+        futures::future::join_all(runs).await
+    }
+
+    async fn execute_limited(&self, permits: &tokio::sync::Semaphore, call: &ToolCall, approved: bool) -> Result<ToolOutput> {
+        let _permit = permits.acquire().await?;
+        self.execute(call, approved).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyConfig;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    /// Sleeps for `ms` and echoes it, counting how many calls run at once.
+    #[derive(Default)]
+    struct Sleepy {
+        running: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Tool for Arc<Sleepy> {
+        fn definition(&self) -> ToolDef {
+            ToolDef { name: "sleepy".into(), description: String::new(), parameters: json!({}) }
+        }
+
+        async fn execute(&self, args: serde_json::Value, _ctx: &ToolContext) -> Result<ToolOutput> {
+            let now = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            let ms = args["ms"].as_u64().unwrap();
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(ToolOutput { success: true, output: ms.to_string() })
+        }
+
+        fn needs_approval(&self) -> bool { false }
+    }
+
+    struct Stall;
+
+    impl SyncTool for Stall {
+        fn definition(&self) -> ToolDef {
+            ToolDef { name: "stall".into(), description: String::new(), parameters: json!({}) }
+        }
+
+        fn execute(&self, _args: serde_json::Value, _ctx: &ToolContext) -> Result<ToolOutput> {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(ToolOutput { success: true, output: "done".into() })
+        }
+
+        fn needs_approval(&self) -> bool { false }
+    }
+
+    fn call(name: &str, arguments: serde_json::Value) -> (ToolCall, bool) {
+        (ToolCall { id: String::new(), name: name.into(), arguments }, false)
+    }
+
+    #[tokio::test]
+    async fn execute_all_keeps_call_order_within_the_limit() {
+        let sleepy = Arc::new(Sleepy::default());
+        let mut tools = ToolRegistry::new();
+        tools.register(Box::new(sleepy.clone()));
+        tools.set_concurrency(2);
+
+        let delays = [120, 10, 80, 5, 40, 1];
+        let calls: Vec<_> = delays.iter().map(|ms| call("sleepy", json!({ "ms": ms }))).collect();
+        let outputs: Vec<String> = tools.execute_all(&calls).await
+            .into_iter()
+            .map(|r| r.unwrap().output)
+            .collect();
+
+        assert_eq!(outputs, delays.map(|ms| ms.to_string()));
+        assert_eq!(sleepy.peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn sync_tools_do_not_block_the_runtime() {
+        let mut tools = ToolRegistry::new();
+        tools.register_sync(Stall);
+        let started = Instant::now();
+
+        // The test runtime has one thread: run inline, the stall would hold the timer back.
+        let calls = [call("stall", json!({}))];
+        let (results, ticked) = tokio::join!(
+            async {
+                let results = tools.execute_all(&calls).await;
+                (results, started.elapsed())
+            },
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                started.elapsed()
+            },
+        );
+        let (results, finished) = results;
+
+        assert_eq!(results[0].as_ref().unwrap().output, "done");
+        assert!(ticked < Duration::from_millis(200), "{ticked:?}");
+        assert!(finished >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn allowed_command_with_env_needs_approval() {
+        let config: PolicyConfig = serde_json::from_value(json!({ "channels": { "whatsapp": { "tools": {
+            "shell_exec": { "default": "deny", "rules": [{ "regex": "^git (status|log|diff)\\b", "action": "allow" }] },
+        } } } })).unwrap();
+        let mut tools = ToolRegistry::new();
+        tools.register_defaults();
+        tools.set_policy(config.channel("whatsapp").unwrap());
+
+        let marker = std::env::temp_dir().join(format!("kova-pwned-{}", uuid::Uuid::new_v4()));
+        let call = ToolCall {
+            id: String::new(),
+            name: "shell_exec".into(),
+            arguments: json!({
+                "command": "git status",
+                "env": {
+                    "GIT_CONFIG_COUNT": "1",
+                    "GIT_CONFIG_KEY_0": "core.fsmonitor",
+                    "GIT_CONFIG_VALUE_0": format!("touch {}; false", marker.display()),
+                },
+            }),
+        };
+        let output = tools.execute(&call, false).await.unwrap();
+        assert!(!output.success);
+        assert!(output.output.contains("needs approval"), "{}", output.output);
+        assert!(!marker.exists());
     }
 }
@@ -1,10 +1,113 @@
-use super::{Tool, ToolDef, ToolOutput};
+use super::{Tool, ToolContext, ToolDef, ToolOutput};
+use crate::config::ShellConfig;
 use anyhow::Result;
+use async_trait::async_trait;
 use serde_json::json;
-use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
 
-pub struct ShellExec;
+/// How long to wait for the pipes to close after the process group was killed.
+const DRAIN_GRACE: Duration = Duration::from_secs(2);
 
+#[derive(Default)]
+pub struct ShellExec {
+    config: ShellConfig,
+}
+
+impl ShellExec {
+    pub fn new(config: ShellConfig) -> Self {
+        Self { config }
+    }
+
+    async fn run(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
+        let cmd = args["command"].as_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing 'command' argument"))?;
+        let timeout = args["timeout_secs"].as_u64()
+            .unwrap_or(self.config.default_timeout_secs)
+            .min(self.config.max_timeout_secs);
+
+        let cwd = match args["cwd"].as_str() {
+            Some(dir) => match ctx.workspace.resolve(dir) {
+                Ok(dir) => dir,
+                Err(e) => return Ok(ToolOutput::error(e)),
+            },
+            None => ctx.workspace.cwd().to_path_buf(),
+        };
+
+        let mut command = Command::new("sh");
+        command.arg("-c")
+            .arg(cmd)
+            .current_dir(&cwd)
+            .stdin(if args["stdin"].is_string() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Own process group, so a timeout can kill everything the command started.
+            .process_group(0)
+            .kill_on_drop(true);
+        if let Some(env) = args["env"].as_object() {
+            for (key, value) in env {
+                match value.as_str() {
+                    Some(v) => command.env(key, v),
+                    None => command.env(key, value.to_string()),
+                };
+            }
+        }
+
+        let mut child = command.spawn()?;
+        let pgid = child.id();
+
+        if let (Some(input), Some(mut stdin)) = (args["stdin"].as_str(), child.stdin.take()) {
+            let input = input.to_string();
+            tokio::spawn(async move {
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
+
+        let limit = self.config.max_output_bytes;
+        let stdout = tokio::spawn(read_capped(child.stdout.take(), limit));
+        let stderr = tokio::spawn(read_capped(child.stderr.take(), limit));
+
+        let (status, timed_out) = match tokio::time::timeout(Duration::from_secs(timeout), child.wait()).await {
+            Ok(status) => (Some(status?), false),
+            Err(_) => {
+                kill_group(pgid);
+                let _ = child.kill().await;
+                (child.wait().await.ok(), true)
+            }
+        };
+        // Background jobs would otherwise keep the pipes open.
+        kill_group(pgid);
+
+        let stdout = collect(stdout).await;
+        let stderr = collect(stderr).await;
+
+        let mut output = stdout;
+        if !stderr.is_empty() {
+            output.push_str("\n[stderr]\n");
+            output.push_str(&stderr);
+        }
+        if timed_out {
+            output.push_str(&format!("\n[timed out after {timeout}s, process group killed]"));
+        } else if let Some(status) = status {
+            match (status.code(), status.signal()) {
+                (Some(0), _) => {}
+                (Some(code), _) => output.push_str(&format!("\n[exit code {code}]")),
+                (None, Some(signal)) => output.push_str(&format!("\n[killed by signal {signal}]")),
+                (None, None) => {}
+            }
+        }
+
+        Ok(ToolOutput {
+            success: !timed_out && status.is_some_and(|s| s.success()),
+            output,
+        })
+    }
+}
+
+#[async_trait]
 impl Tool for ShellExec {
     fn definition(&self) -> ToolDef {
         ToolDef {
@@ -13,33 +116,183 @@ impl Tool for ShellExec {
             parameters: json!({
                 "type": "object",
                 "properties": {
-                    "command": { "type": "string", "description": "Shell command to execute" }
+                    "command": { "type": "string", "description": "Shell command to execute" },
+                    "cwd": { "type": "string", "description": "Working directory (defaults to the session directory)" },
+                    "env": {
+                        "type": "object",
+                        "description": "Extra environment variables",
+                        "additionalProperties": { "type": "string" }
+                    },
+                    "stdin": { "type": "string", "description": "Text to pipe to the command's stdin" },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": format!("Timeout in seconds (default {}, max {})",
+                            self.config.default_timeout_secs, self.config.max_timeout_secs)
+                    }
                 },
                 "required": ["command"]
             }),
         }
     }
 
-    fn execute(&self, args: serde_json::Value) -> Result<ToolOutput> {
-        let cmd = args["command"].as_str()
-            .ok_or_else(|| anyhow::anyhow!("Missing 'command' argument"))?;
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
+        self.run(args, ctx).await
+    }
+}
 
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .output()?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let combined = if stderr.is_empty() {
-            stdout.to_string()
-        } else {
-            format!("{stdout}\n[stderr]\n{stderr}")
+/// Reads a pipe to the end, keeping the first and last `limit / 2` bytes.
+async fn read_capped<R: AsyncRead + Unpin>(pipe: Option<R>, limit: usize) -> String {
+    let Some(mut pipe) = pipe else { return String::new() };
+    let half = limit / 2;
+    let mut head = Vec::new();
+    let mut tail = std::collections::VecDeque::new();
+    let mut total = 0usize;
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let n = match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
         };
+        total += n;
+        let mut chunk = &buf[..n];
+        if head.len() < half {
+            let take = chunk.len().min(half - head.len());
+            head.extend_from_slice(&chunk[..take]);
+            chunk = &chunk[take..];
+        }
+        tail.extend(chunk);
+        while tail.len() > half {
+            tail.pop_front();
+        }
+    }
 
-        Ok(ToolOutput {
-            success: output.status.success(),
-            output: combined,
-        })
+    let tail: Vec<u8> = tail.into();
+    let dropped = total - head.len() - tail.len();
+    if dropped == 0 {
+        head.extend_from_slice(&tail);
+        return String::from_utf8_lossy(&head).into_owned();
+    }
+    format!(
+        "{}\n[... {dropped} bytes omitted ...]\n{}",
+        String::from_utf8_lossy(&head),
+        String::from_utf8_lossy(&tail),
+    )
+}
+
+fn kill_group(pgid: Option<u32>) {
+    if let Some(pgid) = pgid {
+        // SAFETY: plain syscall; a negative pid addresses the whole process group.
+        unsafe { libc::kill(-(pgid as i32), libc::SIGKILL) };
+    }
+}
+
+/// Waits for a reader task, giving up if a stray process still holds the pipe open.
+async fn collect(mut reader: tokio::task::JoinHandle<String>) -> String {
+    match tokio::time::timeout(DRAIN_GRACE, &mut reader).await {
+        Ok(Ok(text)) => text,
+        _ => {
+            reader.abort();
+            String::from("[output unavailable]")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::tools::workspace::Workspace;
+    use std::time::Instant;
+
+    fn shell(max_output_bytes: usize) -> ShellExec {
+        ShellExec::new(ShellConfig { max_output_bytes, ..ShellConfig::default() })
+    }
+
+    fn context(dir: &TempDir) -> ToolContext {
+        dir.write("root/notes.txt", "notes");
+        let root = dir.path().join("root");
+        ToolContext { workspace: Workspace::new(std::slice::from_ref(&root), None).unwrap() }
+    }
+
+    /// Gone or a zombie waiting to be reaped, within a second of the kill.
+    async fn is_dead(pid: &str) -> bool {
+        for _ in 0..20 {
+            let dead = match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+                Ok(stat) => stat.rsplit(')').next().is_some_and(|rest| rest.trim_start().starts_with('Z')),
+                Err(_) => true,
+            };
+            if dead {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_the_process_group_promptly() {
+        let dir = TempDir::new();
+        let ctx = context(&dir);
+        let started = Instant::now();
+        let args = json!({ "command": "sleep 1000 & echo $!; wait", "timeout_secs": 1 });
+        let output = shell(10_000).execute(args, &ctx).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+        assert!(!output.success);
+        assert!(output.output.contains("[timed out after 1s, process group killed]"), "{}", output.output);
+        let pid = output.output.lines().next().unwrap().trim();
+        assert!(is_dead(pid).await, "sleep {pid} survived");
+    }
+
+    #[tokio::test]
+    async fn background_jobs_do_not_hold_the_call_open() {
+        let dir = TempDir::new();
+        let ctx = context(&dir);
+        let started = Instant::now();
+        let output = shell(10_000).execute(json!({ "command": "sleep 1000 & echo $!" }), &ctx).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5), "{:?}", started.elapsed());
+        assert!(output.success, "{}", output.output);
+        assert!(is_dead(output.output.trim()).await, "{}", output.output);
+    }
+
+    #[tokio::test]
+    async fn long_output_keeps_head_and_tail() {
+        let dir = TempDir::new();
+        let ctx = context(&dir);
+        let output = shell(1000).execute(json!({ "command": "yes | head -c 100000" }), &ctx).await.unwrap();
+
+        assert!(output.success, "{}", output.output);
+        assert!(output.output.contains("\n[... 99000 bytes omitted ...]\n"), "{}", output.output);
+        assert!(output.output.starts_with("y\ny\n"));
+        assert!(output.output.len() < 1100);
+    }
+
+    #[tokio::test]
+    async fn reports_exit_codes_and_signals() {
+        let dir = TempDir::new();
+        let ctx = context(&dir);
+        let output = shell(10_000).execute(json!({ "command": "kill -9 $$" }), &ctx).await.unwrap();
+        assert!(!output.success);
+        assert!(output.output.ends_with("[killed by signal 9]"), "{}", output.output);
+
+        let output = shell(10_000).execute(json!({ "command": "echo oops >&2; exit 3" }), &ctx).await.unwrap();
+        assert!(!output.success);
+        assert_eq!(output.output, "\n[stderr]\noops\n\n[exit code 3]");
+    }
+
+    #[tokio::test]
+    async fn cwd_stays_inside_the_workspace() {
+        let dir = TempDir::new();
+        let ctx = context(&dir);
+        let output = shell(10_000).execute(json!({ "command": "cat notes.txt", "cwd": "." }), &ctx).await.unwrap();
+        assert_eq!(output.output, "notes");
+
+        for cwd in ["..", "/tmp", dir.path().to_str().unwrap()] {
+            let output = shell(10_000).execute(json!({ "command": "pwd", "cwd": cwd }), &ctx).await.unwrap();
+            assert!(!output.success, "{cwd}");
+            assert!(output.output.contains("outside the allowed directories"), "{}", output.output);
+        }
     }
 }
@@ -0,0 +1,211 @@
+use crate::policy::normalize_path;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Directories file tools may touch, plus the directory relative paths resolve against.
+///
+/// Roots are canonicalised up front and every path is resolved through symlinks
+/// before the check, so `..` and symlinks can't point outside the roots.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    roots: Vec<PathBuf>,
+    cwd: PathBuf,
+}
+
+impl Workspace {
+    /// `cwd` defaults to the first root and must lie inside the roots.
+    pub fn new(roots: &[PathBuf], cwd: Option<&Path>) -> Result<Self> {
+        let roots = roots.iter()
+            .map(|r| r.canonicalize().with_context(|| format!("workspace root {}", r.display())))
+            .collect::<Result<Vec<_>>>()?;
+        let cwd = match cwd {
+            Some(dir) => dir.canonicalize().with_context(|| format!("workspace cwd {}", dir.display()))?,
+            None => roots.first().cloned().ok_or_else(|| anyhow::anyhow!("workspace needs at least one root"))?,
+        };
+        let workspace = Self { roots, cwd };
+        if !workspace.contains(&workspace.cwd) {
+            anyhow::bail!("workspace cwd {} is outside the allowed roots", workspace.cwd.display());
+        }
+        Ok(workspace)
+    }
+
+    /// A workspace rooted at the process working directory.
+    pub fn current_dir() -> Result<Self> {
+        Self::new(&[std::env::current_dir()?], None)
+    }
+
+    /// A workspace without roots, which rejects every path.
+    pub fn deny_all() -> Self {
+        Self { roots: Vec::new(), cwd: PathBuf::from("/") }
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Changes the directory relative paths resolve against.
+    pub fn set_cwd(&mut self, dir: &str) -> Result<(), String> {
+        let dir = self.resolve(dir)?;
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()));
+        }
+        self.cwd = dir;
+        Ok(())
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Resolves `path` to a real path inside the workspace. The path itself may not
+    /// exist yet (for writes); its closest existing ancestor is resolved instead.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let requested = Path::new(path);
+        let joined = if requested.is_absolute() {
+            requested.to_path_buf()
+        } else {
+            self.cwd.join(requested)
+        };
+
+        let mut existing = normalize_path(&joined);
+        let mut missing = Vec::new();
+        let real = loop {
+            match existing.canonicalize() {
+                Ok(real) => break real,
+                Err(_) if existing.symlink_metadata().is_ok() => {
+                    return Err(format!("{path} is a broken symlink"));
+                }
+                Err(_) => {
+                    let Some(name) = existing.file_name() else {
+                        return Err(format!("{path} can't be resolved"));
+                    };
+                    missing.push(name.to_os_string());
+                    existing.pop();
+                }
+            }
+        };
+        let real = missing.into_iter().rev().fold(real, |p, name| p.join(name));
+
+        if self.contains(&real) {
+            Ok(real)
+        } else {
+            let roots: Vec<_> = self.roots.iter().map(|r| r.display().to_string()).collect();
+            Err(format!("{path} is outside the allowed directories ({})", roots.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::tools::{fs::WriteFile, SyncTool, ToolContext};
+    use std::os::unix::fs::symlink;
+
+    /// `dir/root` is the only root, with `dir/root/sub` as cwd; `dir/secret.txt` lies outside.
+    fn workspace(dir: &TempDir) -> Workspace {
+        dir.write("secret.txt", "api key");
+        dir.write("root/sub/notes.txt", "notes");
+        let root = dir.path().join("root");
+        Workspace::new(std::slice::from_ref(&root), Some(&root.join("sub"))).unwrap()
+    }
+
+    fn rejected(workspace: &Workspace, path: &str) -> String {
+        workspace.resolve(path).expect_err(path)
+    }
+
+    #[test]
+    fn resolves_relative_paths_against_cwd() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        let root = dir.path().join("root");
+        assert_eq!(workspace.resolve("notes.txt").unwrap(), root.join("sub/notes.txt"));
+        assert_eq!(workspace.resolve("../sub/./notes.txt").unwrap(), root.join("sub/notes.txt"));
+        assert_eq!(workspace.resolve(root.join("x").to_str().unwrap()).unwrap(), root.join("x"));
+    }
+
+    #[test]
+    fn rejects_absolute_paths_outside() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        let error = rejected(&workspace, dir.path().join("secret.txt").to_str().unwrap());
+        assert!(error.contains("outside the allowed directories"), "{error}");
+        rejected(&workspace, "/etc/passwd");
+        rejected(&workspace, "/");
+    }
+
+    #[test]
+    fn rejects_dot_dot_out_of_the_root() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        rejected(&workspace, "../../secret.txt");
+        rejected(&workspace, "../..");
+        rejected(&workspace, "missing/../../../secret.txt");
+        rejected(&workspace, "../../../../../../etc/passwd");
+    }
+
+    #[test]
+    fn rejects_symlinks_to_outside() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        let sub = dir.path().join("root/sub");
+        symlink(dir.path(), sub.join("up")).unwrap();
+        symlink(dir.path().join("secret.txt"), sub.join("key.txt")).unwrap();
+        symlink(sub.join("notes.txt"), sub.join("inside.txt")).unwrap();
+
+        rejected(&workspace, "up/secret.txt");
+        rejected(&workspace, "key.txt");
+        // Not there yet, but its existing ancestor lies outside.
+        rejected(&workspace, "up/new/file.txt");
+        assert_eq!(workspace.resolve("inside.txt").unwrap(), sub.join("notes.txt"));
+    }
+
+    #[test]
+    fn rejects_broken_symlinks() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        let sub = dir.path().join("root/sub");
+        // A write through it would create the file outside.
+        symlink(dir.path().join("planted.txt"), sub.join("dangling")).unwrap();
+        symlink(sub.join("gone.txt"), sub.join("dangling_inside")).unwrap();
+
+        assert!(rejected(&workspace, "dangling").contains("broken symlink"));
+        assert!(rejected(&workspace, "dangling_inside").contains("broken symlink"));
+        let ctx = ToolContext { workspace };
+        let output = WriteFile.execute(serde_json::json!({ "path": "dangling", "content": "x" }), &ctx).unwrap();
+        assert!(!output.success);
+        assert!(!dir.path().join("planted.txt").exists());
+    }
+
+    #[test]
+    fn writes_to_missing_nested_paths() {
+        let dir = TempDir::new();
+        let workspace = workspace(&dir);
+        let target = dir.path().join("root/sub/new/deep/file.txt");
+        assert_eq!(workspace.resolve("new/deep/file.txt").unwrap(), target);
+
+        let ctx = ToolContext { workspace };
+        let output = WriteFile.execute(serde_json::json!({ "path": "new/deep/file.txt", "content": "hi" }), &ctx).unwrap();
+        assert!(output.success, "{}", output.output);
+        assert_eq!(std::fs::read_to_string(target).unwrap(), "hi");
+    }
+
+    #[test]
+    fn set_cwd_stays_inside_the_roots() {
+        let dir = TempDir::new();
+        let mut workspace = workspace(&dir);
+        let root = dir.path().join("root");
+        assert!(workspace.set_cwd("../..").is_err());
+        assert!(workspace.set_cwd("notes.txt").unwrap_err().contains("not a directory"));
+        assert_eq!(workspace.cwd(), root.join("sub"));
+
+        workspace.set_cwd("..").unwrap();
+        assert_eq!(workspace.cwd(), root);
+        assert_eq!(workspace.resolve("sub/notes.txt").unwrap(), root.join("sub/notes.txt"));
+        assert!(Workspace::new(&[root], Some(dir.path())).is_err());
+    }
+}
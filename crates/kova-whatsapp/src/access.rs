@@ -0,0 +1,73 @@
+//! Who may talk to kova over WhatsApp, and how often.
+
+use kova_core::config::{SenderRole, WhatsAppConfig};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+pub enum Access {
+    Allowed(SenderRole),
+    /// The chat is not in `whatsapp.senders`.
+    Unknown,
+    /// The chat used up its role's hourly messages.
+    RateLimited(SenderRole),
+}
+
+pub struct AccessControl {
+    senders: HashMap<String, SenderRole>,
+    limits: HashMap<SenderRole, Option<u32>>,
+    /// Arrival times of recent messages per chat, for the sliding rate window.
+    recent: HashMap<String, VecDeque<Instant>>,
+    unknown_reply: Option<String>,
+    /// Unknown chats that already got `unknown_reply`.
+    answered: HashSet<String>,
+}
+
+impl AccessControl {
+    pub fn new(config: &WhatsAppConfig) -> Self {
+        let limits = [SenderRole::Owner, SenderRole::Trusted, SenderRole::Guest]
+            .into_iter()
+            .map(|role| (role, config.role(role).messages_per_hour))
+            .collect();
+        Self {
+            senders: config.senders.clone(),
+            limits,
+            recent: HashMap::new(),
+            unknown_reply: config.unknown_reply.clone(),
+            answered: HashSet::new(),
+        }
+    }
+
+    /// The role of `jid`, without counting a message.
+    pub fn role(&self, jid: &str) -> Option<SenderRole> {
+        self.senders.get(jid).copied()
+    }
+
+    /// Checks a message from `jid` and counts it against the chat's rate limit.
+    pub fn check(&mut self, jid: &str) -> Access {
+        let Some(&role) = self.senders.get(jid) else {
+            return Access::Unknown;
+        };
+        let Some(limit) = self.limits.get(&role).copied().flatten() else {
+            return Access::Allowed(role);
+        };
+
+        let now = Instant::now();
+        let recent = self.recent.entry(jid.to_string()).or_default();
+        while recent.front().is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() >= limit as usize {
+            return Access::RateLimited(role);
+        }
+        recent.push_back(now);
+        Access::Allowed(role)
+    }
+
+    /// The reply for an unknown chat, given once per chat.
+    pub fn unknown_reply(&mut self, jid: &str) -> Option<&str> {
+        let reply = self.unknown_reply.as_deref()?;
+        self.answered.insert(jid.to_string()).then_some(reply)
+    }
+}
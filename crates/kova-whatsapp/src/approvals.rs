@@ -0,0 +1,169 @@
+//! Tool approval by the owner over WhatsApp.
+//!
+//! A call the policy marks `ask` pauses its chat's agent and sends the owner the
+//! tool, its arguments and a short id. The owner answers `y <id>` or `n <id> [reason]`
+//! from their own chat; a message naming no pending id is an ordinary message, so
+//! chat like "no face" still reaches the agent. Unanswered requests are denied after
+//! the timeout. Pending
+//! requests are kept here, not in the bridge, so an answer sent after a bridge
+//! restart still reaches the waiting agent.
+
+use crate::bridge::BridgeSender;
+use async_trait::async_trait;
+use kova_core::approval::{Approval, Approver};
+use kova_core::text;
+use kova_core::tools::ToolCall;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Arguments shown in a request are cut to this length.
+const ARGS_PREVIEW_CHARS: usize = 1000;
+
+const ID_LEN: usize = 4;
+
+pub struct Approvals {
+    owner: String,
+    timeout: Duration,
+    bridge: BridgeSender,
+    /// Waiting requests by id.
+    pending: Mutex<HashMap<String, oneshot::Sender<Approval>>>,
+}
+
+impl Approvals {
+    pub fn new(owner: String, timeout: Duration, bridge: BridgeSender) -> Arc<Self> {
+        Arc::new(Self { owner, timeout, bridge, pending: Mutex::default() })
+    }
+
+    /// The JID requests go to and answers come from.
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Asks the owner about `call`, made in the chat labelled `chat`.
+    async fn request(&self, chat: &str, call: &ToolCall) -> Approval {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let id = loop {
+                let id = uuid::Uuid::new_v4().simple().to_string()[..ID_LEN].to_string();
+                if !pending.contains_key(&id) {
+                    break id;
+                }
+            };
+            pending.insert(id.clone(), tx);
+            id
+        };
+        let prompt = format!(
+            "Approval needed [{id}]\nChat: {chat}\nTool: {}\n{}\n\nReply \"y {id}\" to run it or \"n {id}\" to refuse. \
+             No answer within {} min counts as no.",
+            call.name,
+            text::truncate(&call.arguments.to_string(), ARGS_PREVIEW_CHARS),
+            self.timeout.as_secs().div_ceil(60),
+        );
+        tracing::info!("waiting for approval {id} of {}", call.name);
+        if let Err(e) = self.bridge.send_text(&self.owner, &prompt).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Approval::deny(format!("could not ask the owner: {e}"));
+        }
+
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(approval)) => approval,
+            Ok(Err(_)) => Approval::deny("approval request dropped"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                tracing::warn!("approval {id} of {} timed out", call.name);
+                let _ = self.bridge.send_text(&self.owner, &format!("Approval [{id}] timed out, denied.")).await;
+                Approval::deny(format!("the owner did not answer within {}s", self.timeout.as_secs()))
+            }
+        }
+    }
+
+    /// Handles a message from the owner. Returns whether it answered a pending request.
+    pub async fn answer(&self, text: &str) -> bool {
+        let Some((approve, id, reason)) = parse_answer(text) else {
+            return false;
+        };
+        let Some(pending) = self.pending.lock().unwrap().remove(&id) else {
+            return false;
+        };
+        let approval = if approve {
+            Approval::Approve
+        } else {
+            Approval::deny(reason.unwrap_or_else(|| "denied by the owner".into()))
+        };
+        let _ = pending.send(approval);
+        let reply = format!("[{id}] {}", if approve { "approved" } else { "denied" });
+        if let Err(e) = self.bridge.send_text(&self.owner, &reply).await {
+            tracing::warn!("could not confirm approval answer: {e}");
+        }
+        true
+    }
+}
+
+/// `y <id>`, `yes <id>`, `n <id> [reason]` or `no <id> [reason]`.
+fn parse_answer(text: &str) -> Option<(bool, String, Option<String>)> {
+    let mut words = text.trim().splitn(3, char::is_whitespace);
+    let approve = match words.next()?.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => return None,
+    };
+    let id = words.next()?.trim_matches(|c: char| !c.is_ascii_alphanumeric()).to_lowercase();
+    if id.len() != ID_LEN || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let reason = words.next().map(str::trim).filter(|r| !r.is_empty()).map(String::from);
+    Some((approve, id, reason))
+}
+
+/// Approver of one chat's agent.
+pub struct RemoteApprover {
+    approvals: Arc<Approvals>,
+    chat: String,
+}
+
+impl RemoteApprover {
+    pub fn new(approvals: Arc<Approvals>, chat: String) -> Self {
+        Self { approvals, chat }
+    }
+}
+
+#[async_trait]
+impl Approver for RemoteApprover {
+    async fn approve(&self, call: &ToolCall, _needs_approval: bool) -> Approval {
+        self.approvals.request(&self.chat, call).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(approve: bool, id: &str, reason: Option<&str>) -> Option<(bool, String, Option<String>)> {
+        Some((approve, id.into(), reason.map(String::from)))
+    }
+
+    #[test]
+    fn parses_answers_with_ids_and_reasons() {
+        assert_eq!(parse_answer("y 3fa2"), answer(true, "3fa2", None));
+        assert_eq!(parse_answer("  Yes [3FA2]. "), answer(true, "3fa2", None));
+        assert_eq!(parse_answer("N 3FA2 too risky, use ls"), answer(false, "3fa2", Some("too risky, use ls")));
+        assert_eq!(parse_answer("no 3fa2 \n "), answer(false, "3fa2", None));
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        for text in ["y", "y 3fa", "y 3fa21", "n 3fg2", "yes 3f-a2", "y 3fa2b1c0 ok"] {
+            assert_eq!(parse_answer(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn plain_chat_is_not_an_answer() {
+        for text in ["yes please", "no idea", "nope 3fa2", "why 3fa2", "3fa2 y", "", "hello there"] {
+            assert_eq!(parse_answer(text), None, "{text}");
+        }
+    }
+}
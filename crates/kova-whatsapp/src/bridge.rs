@@ -1,21 +1,63 @@
 use anyhow::Result;
+use kova_core::text;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{Child, Command};
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::{mpsc, oneshot};
 
+/// Longest text sent as one WhatsApp message; longer replies go out in parts.
+const MAX_MESSAGE_CHARS: usize = 4000;
+
+/// Pause between the parts of a long reply.
+const PART_DELAY: Duration = Duration::from_millis(800);
+
+/// How many sent message ids are remembered for echo suppression.
+const SENT_IDS_CAPACITY: usize = 512;
+
+/// Version of the JSON-lines protocol spoken with the Node bridge (see `bridge/PROTOCOL.md`).
+/// Must match `PROTOCOL` in the bridge scripts.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long a fresh bridge may take to say `hello`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a bridge that closed its stdout gets to exit before it is killed.
+const EXIT_GRACE: Duration = Duration::from_secs(5);
+
+/// How long `send_file` waits for the bridge to confirm.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Events the bridge writes to stdout, one JSON object per line.
 #[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
 pub enum BridgeEvent {
-    #[serde(rename = "connected")]
-    Connected,
-    #[serde(rename = "disconnected")]
+    /// First line of every bridge process.
+    Hello {
+        protocol: u32,
+        #[serde(default)]
+        bridge: String,
+    },
+    /// Logged in as `jid` (with a device suffix, e.g. `905551234567:12@s.whatsapp.net`);
+    /// `lid` is the account's privacy id, which group mentions may use instead.
+    Connected {
+        #[serde(default)]
+        jid: Option<String>,
+        #[serde(default)]
+        lid: Option<String>,
+    },
     Disconnected { reason: String },
-    #[serde(rename = "message")]
     Message {
+        /// The chat: a contact, or a group (`...@g.us`).
         jid: String,
+        /// Who wrote a group message.
+        #[serde(default)]
+        participant: Option<String>,
         text: String,
         #[serde(default)]
         push_name: String,
@@ -23,85 +65,600 @@ pub enum BridgeEvent {
         message_id: String,
         #[serde(default)]
         from_me: bool,
+        /// Accounts @-mentioned in the text.
+        #[serde(default)]
+        mentions: Vec<String>,
+        /// The message this one replies to.
+        #[serde(default)]
+        quoted: Option<Quoted>,
+        /// Media sent with the message; `text` is then its caption.
+        #[serde(default)]
+        attachment: Option<Box<Attachment>>,
     },
-    #[serde(rename = "qr")]
     Qr { data: String },
-    #[serde(rename = "sent")]
-    Sent { jid: String },
-    #[serde(rename = "error")]
-    Error { message: String },
+    /// Command `id` succeeded. For `send`, `message_id` is the WhatsApp id of the
+    /// sent message; its echo comes back with the same id.
+    Ack {
+        id: String,
+        #[serde(default)]
+        message_id: Option<String>,
+    },
+    /// Command `id` failed, or the bridge hit an error of its own when `id` is absent.
+    /// With `retry` the command was not carried out because the bridge is not connected;
+    /// the supervisor sends it again after the next `connected`.
+    Error {
+        #[serde(default)]
+        id: Option<String>,
+        message: String,
+        #[serde(default)]
+        retry: bool,
+    },
 }
 
+/// The message a reply refers to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quoted {
+    pub message_id: String,
+    /// Author of the quoted message, in groups.
+    #[serde(default)]
+    pub participant: Option<String>,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// A media file the bridge received with a message.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    #[serde(default)]
+    pub mime_type: String,
+    /// Original name of a document.
+    #[serde(default)]
+    pub file_name: Option<String>,
+    /// Where the bridge saved the file; `None` when it was not downloaded.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// Length of a voice note.
+    #[serde(default)]
+    pub seconds: Option<u32>,
+    /// Why the file was not downloaded.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentKind {
+    Image,
+    Document,
+    Audio,
+    #[serde(other)]
+    Other,
+}
+
+/// Commands written to the bridge's stdin. Every command carries a request id that
+/// comes back in its `ack` or `error` event.
 #[derive(Debug, Serialize)]
-struct BridgeCommand {
-    r#type: String,
-    jid: String,
-    text: String,
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+enum BridgeCommand {
+    Send {
+        id: String,
+        jid: String,
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        quoted: Option<ReplyTo>,
+    },
+    SendFile {
+        id: String,
+        jid: String,
+        path: PathBuf,
+        kind: AttachmentKind,
+        mime_type: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file_name: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        caption: Option<String>,
+    },
+    Presence { id: String, jid: String, state: Presence },
+    Read {
+        id: String,
+        jid: String,
+        #[serde(flatten)]
+        key: MessageKey,
+    },
+    React {
+        id: String,
+        jid: String,
+        #[serde(flatten)]
+        key: MessageKey,
+        emoji: String,
+    },
+}
+
+impl BridgeCommand {
+    fn id(&self) -> &str {
+        match self {
+            BridgeCommand::Send { id, .. }
+            | BridgeCommand::SendFile { id, .. }
+            | BridgeCommand::Presence { id, .. }
+            | BridgeCommand::Read { id, .. }
+            | BridgeCommand::React { id, .. } => id,
+        }
+    }
+}
+
+/// Identifies a received message, to mark it read, react to it or quote it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageKey {
+    pub message_id: String,
+    /// Author of a group message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub participant: Option<String>,
+    pub from_me: bool,
+}
+
+/// The message a reply quotes, with the text shown in the quote.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplyTo {
+    #[serde(flatten)]
+    pub key: MessageKey,
+    pub text: String,
+}
+
+/// What a chat shows under its name.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    /// "typing..."
+    Composing,
+    Paused,
+}
+
+/// A serialised command with its request id, as queued for the supervisor.
+#[derive(Debug, Clone)]
+pub struct QueuedCommand {
+    pub id: String,
+    pub line: String,
 }
 
-pub struct BaileysBridge {
+/// A file to send with `BridgeSender::send_file`.
+pub struct OutgoingFile {
+    pub path: PathBuf,
+    pub kind: AttachmentKind,
+    pub mime_type: String,
+    pub file_name: Option<String>,
+}
+
+/// How the bridge answered a command: the sent message id, or its error.
+type Reply = std::result::Result<Option<String>, String>;
+
+/// How to start the bridge process.
+pub struct BridgeLaunch {
+    pub bridge_dir: PathBuf,
+    /// Script run under node, relative to `bridge_dir`.
+    pub script: String,
+    pub auth_dir: PathBuf,
+    /// Where incoming media is saved.
+    pub media_dir: PathBuf,
+    pub max_media_bytes: u64,
+    /// Chats whose media the bridge downloads.
+    pub media_from: Vec<String>,
+}
+
+/// One running bridge process, past its handshake.
+pub struct BridgeProcess {
     child: Child,
-    stdin_tx: mpsc::Sender<String>,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
 }
 
-impl BaileysBridge {
-    pub async fn spawn(
-        bridge_dir: &Path,
-        auth_dir: &Path,
-    ) -> Result<(Self, mpsc::Receiver<BridgeEvent>)> {
+impl BridgeProcess {
+    /// Starts the bridge and reads its `hello`. Returns the protocol version it speaks.
+    pub async fn start(launch: &BridgeLaunch) -> Result<(Self, u32)> {
         let mut child = Command::new("node")
-            .arg("baileys_bridge.js")
-            .current_dir(bridge_dir)
-            .env("BAILEYS_AUTH_DIR", auth_dir)
+            .arg(&launch.script)
+            .current_dir(&launch.bridge_dir)
+            .env("BAILEYS_AUTH_DIR", &launch.auth_dir)
+            .env("KOVA_MEDIA_DIR", &launch.media_dir)
+            .env("KOVA_MEDIA_MAX_BYTES", launch.max_media_bytes.to_string())
+            .env("KOVA_MEDIA_FROM", serde_json::to_string(&launch.media_from)?)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
+            .kill_on_drop(true)
             .spawn()?;
 
-        let stdout = child.stdout.take().unwrap();
         let stdin = child.stdin.take().unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let protocol = handshake(&mut lines).await?;
+        Ok((Self { child, stdin, lines }, protocol))
+    }
+
+    /// Next event; `None` once the process closed its stdout.
+    pub async fn next_event(&mut self) -> Option<BridgeEvent> {
+        loop {
+            let line = self.lines.next_line().await.ok()??;
+            match serde_json::from_str::<BridgeEvent>(&line) {
+                Ok(event) => return Some(event),
+                Err(e) => tracing::warn!("bridge parse error: {e}: {line}"),
+            }
+        }
+    }
+
+    pub async fn write(&mut self, line: &str) -> std::io::Result<()> {
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.write_all(b"\n").await?;
+        self.stdin.flush().await
+    }
 
-        let (event_tx, event_rx) = mpsc::channel::<BridgeEvent>(100);
-        let (stdin_tx, mut stdin_rx) = mpsc::channel::<String>(100);
-
-        // Read stdout -> events
-        tokio::spawn(async move {
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
-            while let Ok(Some(line)) = lines.next_line().await {
-                match serde_json::from_str::<BridgeEvent>(&line) {
-                    Ok(event) => { let _ = event_tx.send(event).await; }
-                    Err(e) => tracing::warn!("bridge parse error: {e}: {line}"),
-                }
+    /// Waits for the process to exit and describes how it ended.
+    pub async fn exit(mut self) -> String {
+        drop(self.stdin);
+        match tokio::time::timeout(EXIT_GRACE, self.child.wait()).await {
+            Ok(Ok(status)) => format!("bridge {status}"),
+            Ok(Err(e)) => format!("bridge wait failed: {e}"),
+            Err(_) => {
+                let _ = self.child.kill().await;
+                "bridge closed stdout and was killed".into()
             }
-        });
-
-        // Write stdin <- commands
-        tokio::spawn(async move {
-            let mut stdin = stdin;
-            while let Some(line) = stdin_rx.recv().await {
-                let _ = stdin.write_all(line.as_bytes()).await;
-                let _ = stdin.write_all(b"\n").await;
-                let _ = stdin.flush().await;
+        }
+    }
+}
+
+/// Cloneable handle for sending commands to the bridge from any task. Commands
+/// queue up while the bridge is down or not connected to WhatsApp and go out once
+/// it is back.
+#[derive(Clone)]
+pub struct BridgeSender {
+    commands: mpsc::Sender<QueuedCommand>,
+    next_id: Arc<AtomicU64>,
+    /// Commands whose sender waits for the `ack` or `error`.
+    waiting: Arc<Mutex<HashMap<String, oneshot::Sender<Reply>>>>,
+    sent: SentIds,
+}
+
+impl BridgeSender {
+    pub fn new(commands: mpsc::Sender<QueuedCommand>) -> Self {
+        Self { commands, next_id: Arc::default(), waiting: Arc::default(), sent: SentIds::default() }
+    }
+
+    /// The messages sent through this handle and its clones.
+    pub fn sent_ids(&self) -> SentIds {
+        self.sent.clone()
+    }
+
+    /// Hands the bridge's answer to command `id` to whoever waits for it.
+    pub fn resolve(&self, id: &str, reply: Reply) {
+        self.sent.resolved(id, reply.as_ref().ok().and_then(Option::as_deref));
+        if let Some(waiter) = self.waiting.lock().unwrap().remove(id) {
+            let _ = waiter.send(reply);
+        }
+    }
+
+    /// Commands waiting to be written to the bridge.
+    pub fn queued(&self) -> usize {
+        self.commands.max_capacity() - self.commands.capacity()
+    }
+
+    /// Sends `text`, split into numbered parts if it is too long for one message.
+    pub async fn send_text(&self, jid: &str, text: &str) -> Result<()> {
+        self.reply_text(jid, text, None).await
+    }
+
+    /// Like `send_text`, with the first part quoting `reply_to`.
+    pub async fn reply_text(&self, jid: &str, text: &str, reply_to: Option<&ReplyTo>) -> Result<()> {
+        for (i, part) in text::split_message(text, MAX_MESSAGE_CHARS).iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(PART_DELAY).await;
             }
-        });
+            let quoted = reply_to.filter(|_| i == 0).cloned();
+            let id = self.request_id();
+            self.sent.expect(&id, jid, part);
+            self.command(BridgeCommand::Send { id, jid: jid.into(), text: part.clone(), quoted }).await?;
+        }
+        Ok(())
+    }
+
+    /// Shows or hides "typing..." in `jid`. WhatsApp drops it after a while, so it
+    /// has to be repeated during long work.
+    pub async fn presence(&self, jid: &str, state: Presence) -> Result<()> {
+        self.command(BridgeCommand::Presence { id: self.request_id(), jid: jid.into(), state }).await
+    }
+
+    /// Marks a received message read (blue ticks).
+    pub async fn mark_read(&self, jid: &str, key: &MessageKey) -> Result<()> {
+        self.command(BridgeCommand::Read { id: self.request_id(), jid: jid.into(), key: key.clone() }).await
+    }
 
-        Ok((Self { child, stdin_tx }, event_rx))
+    /// Reacts to a message with `emoji`; an empty one removes kova's reaction.
+    pub async fn react(&self, jid: &str, key: &MessageKey, emoji: &str) -> Result<()> {
+        self.command(BridgeCommand::React { id: self.request_id(), jid: jid.into(), key: key.clone(), emoji: emoji.into() }).await
     }
 
-    pub async fn send_message(&self, jid: &str, text: &str) -> Result<()> {
-        let cmd = BridgeCommand {
-            r#type: "send".into(),
+    /// Sends a file with an optional caption and waits until the bridge has sent it.
+    /// WhatsApp shows no captions on audio, so those follow as a text message.
+    pub async fn send_file(&self, jid: &str, file: OutgoingFile, caption: Option<&str>) -> Result<()> {
+        let audio = file.kind == AttachmentKind::Audio;
+        let id = self.request_id();
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(id.clone(), tx);
+        self.command(BridgeCommand::SendFile {
+            id: id.clone(),
             jid: jid.into(),
-            text: text.into(),
+            path: file.path,
+            kind: file.kind,
+            mime_type: file.mime_type,
+            file_name: file.file_name,
+            caption: caption.filter(|_| !audio).map(String::from),
+        }).await?;
+
+        let Ok(reply) = tokio::time::timeout(REPLY_TIMEOUT, rx).await else {
+            self.waiting.lock().unwrap().remove(&id);
+            anyhow::bail!("no confirmation from the bridge within {}s; the file is still queued", REPLY_TIMEOUT.as_secs());
         };
-        let json = serde_json::to_string(&cmd)?;
-        self.stdin_tx.send(json).await?;
+        reply?.map_err(|message| anyhow::anyhow!("bridge could not send the file: {message}"))?;
+        if let Some(caption) = caption.filter(|_| audio) {
+            self.send_text(jid, caption).await?;
+        }
         Ok(())
     }
 
-    pub async fn kill(&mut self) -> Result<()> {
-        self.child.kill().await?;
+    fn request_id(&self) -> String {
+        format!("r{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    async fn command(&self, command: BridgeCommand) -> Result<()> {
+        let line = serde_json::to_string(&command)?;
+        self.commands.send(QueuedCommand { id: command.id().to_string(), line }).await?;
         Ok(())
     }
 }
+
+/// Reads the bridge's `hello` and returns its protocol version.
+async fn handshake(lines: &mut Lines<BufReader<ChildStdout>>) -> Result<u32> {
+    let line = tokio::time::timeout(HANDSHAKE_TIMEOUT, lines.next_line()).await
+        .map_err(|_| anyhow::anyhow!("bridge did not say hello within {}s", HANDSHAKE_TIMEOUT.as_secs()))??
+        .ok_or_else(|| anyhow::anyhow!("bridge exited before the handshake"))?;
+    match serde_json::from_str::<BridgeEvent>(&line) {
+        Ok(BridgeEvent::Hello { protocol, bridge }) => {
+            tracing::info!("bridge {bridge} speaks protocol v{protocol}");
+            Ok(protocol)
+        }
+        _ => anyhow::bail!("expected a hello from the bridge, got: {line}"),
+    }
+}
+
+/// Ids of messages kova sent, so their `from_me` echoes can be skipped and replies
+/// to them recognised. Holds the most recent `SENT_IDS_CAPACITY` ids.
+///
+/// WhatsApp may deliver the echo of a message before the bridge acks the send with
+/// its id, so text sends are also kept as pending until their ack, and a `from_me`
+/// message with the same chat and text claims one as its echo.
+#[derive(Clone, Default)]
+pub struct SentIds(Arc<Mutex<Sent>>);
+
+#[derive(Default)]
+struct Sent {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    pending: VecDeque<PendingSend>,
+}
+
+struct PendingSend {
+    command_id: String,
+    jid: String,
+    text: String,
+}
+
+impl Sent {
+    fn insert(&mut self, id: &str) {
+        if id.is_empty() || !self.ids.insert(id.to_string()) {
+            return;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > SENT_IDS_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl SentIds {
+    /// Remembers that command `command_id` sends `text` to `jid`.
+    pub fn expect(&self, command_id: &str, jid: &str, text: &str) {
+        let mut sent = self.0.lock().unwrap();
+        sent.pending.push_back(PendingSend { command_id: command_id.into(), jid: jid.into(), text: text.into() });
+        if sent.pending.len() > SENT_IDS_CAPACITY {
+            sent.pending.pop_front();
+        }
+    }
+
+    /// Command `command_id` was answered, with the id of the message it sent if any.
+    pub fn resolved(&self, command_id: &str, message_id: Option<&str>) {
+        let mut sent = self.0.lock().unwrap();
+        sent.pending.retain(|p| p.command_id != command_id);
+        if let Some(message_id) = message_id {
+            sent.insert(message_id);
+        }
+    }
+
+    /// Whether a `from_me` message is the echo of something kova sent. A pending send
+    /// it matches is taken as acked with `message_id`.
+    pub fn is_echo(&self, jid: &str, text: &str, message_id: &str) -> bool {
+        let mut sent = self.0.lock().unwrap();
+        if sent.ids.contains(message_id) {
+            return true;
+        }
+        let Some(i) = sent.pending.iter().position(|p| p.jid == jid && p.text == text) else {
+            return false;
+        };
+        sent.pending.remove(i);
+        sent.insert(message_id);
+        true
+    }
+
+    /// Whether `id` was sent by kova.
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.lock().unwrap().ids.contains(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FIXTURE: &str = include_str!("../../../bridge/fixtures/protocol_v1.jsonl");
+
+    fn fixture() -> Vec<BridgeEvent> {
+        FIXTURE.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+            .collect()
+    }
+
+    #[test]
+    fn fixture_events_parse_with_their_fields() {
+        let events = fixture();
+        assert_eq!(events.len(), 11);
+
+        let BridgeEvent::Connected { jid, lid } = &events[1] else { panic!("{:?}", events[1]) };
+        assert_eq!(jid.as_deref(), Some("905551234567:14@s.whatsapp.net"));
+        assert_eq!(lid.as_deref(), Some("187340291845120:14@lid"));
+
+        let messages: Vec<_> = events.iter().filter(|e| matches!(e, BridgeEvent::Message { .. })).collect();
+        assert_eq!(messages.len(), 5);
+        for message in &messages {
+            let BridgeEvent::Message { message_id, .. } = message else { unreachable!() };
+            assert!(!message_id.is_empty(), "messageId defaulted in {message:?}");
+        }
+
+        let BridgeEvent::Message { push_name, from_me, text, .. } = &events[2] else { panic!("{:?}", events[2]) };
+        assert_eq!(push_name, "Göksel");
+        assert!(!from_me);
+        assert_eq!(text, "Merhaba, disk durumu nasıl?");
+        let BridgeEvent::Message { from_me, message_id, .. } = &events[3] else { panic!("{:?}", events[3]) };
+        assert!(from_me);
+        assert_eq!(message_id, "3EB0D2E3F4A5B6C7D8E9");
+
+        let BridgeEvent::Message { participant, mentions, quoted, .. } = &events[4] else { panic!("{:?}", events[4]) };
+        assert_eq!(participant.as_deref(), Some("905327654321@s.whatsapp.net"));
+        assert_eq!(mentions, &["905551234567@s.whatsapp.net"]);
+        let quoted = quoted.as_ref().unwrap();
+        assert_eq!(quoted.message_id, "ABFD0A9B8C7D6E5F");
+        assert_eq!(quoted.participant.as_deref(), Some("905321112233@s.whatsapp.net"));
+        assert_eq!(quoted.text, "yarınki sunum hazır");
+
+        let BridgeEvent::Message { attachment: Some(document), .. } = &events[5] else { panic!("{:?}", events[5]) };
+        assert_eq!(document.kind, AttachmentKind::Document);
+        assert_eq!(document.mime_type, "application/pdf");
+        assert_eq!(document.file_name.as_deref(), Some("rapor.pdf"));
+        assert_eq!(document.path.as_deref(), Some(std::path::Path::new("/srv/kovaclaw/media/3EB0F1E2D3C4B5A69788.pdf")));
+        assert_eq!(document.size, Some(182044));
+
+        let BridgeEvent::Message { attachment: Some(voice), .. } = &events[6] else { panic!("{:?}", events[6]) };
+        assert_eq!(voice.kind, AttachmentKind::Audio);
+        assert_eq!(voice.mime_type, "audio/ogg");
+        assert_eq!(voice.seconds, Some(14));
+        assert!(voice.path.is_none());
+        assert_eq!(voice.error.as_deref(), Some("Request failed with status code 410"));
+
+        let BridgeEvent::Ack { id, message_id } = &events[7] else { panic!("{:?}", events[7]) };
+        assert_eq!(id, "r1");
+        assert_eq!(message_id.as_deref(), Some("3EB0A1B2C3D4E5F6A7B8"));
+        let BridgeEvent::Error { id, message, retry } = &events[8] else { panic!("{:?}", events[8]) };
+        assert_eq!(id.as_deref(), Some("r2"));
+        assert_eq!(message, "not connected");
+        assert!(!retry);
+        assert!(matches!(&events[9], BridgeEvent::Error { id: None, .. }));
+        assert!(matches!(&events[10], BridgeEvent::Disconnected { reason } if reason == "logged_out"));
+    }
+
+    fn key(participant: Option<&str>) -> MessageKey {
+        MessageKey { message_id: "IN1".into(), participant: participant.map(String::from), from_me: false }
+    }
+
+    fn wire(command: BridgeCommand) -> serde_json::Value {
+        serde_json::to_value(command).unwrap()
+    }
+
+    #[test]
+    fn sent_ids_claim_echoes_in_either_order() {
+        let sent = SentIds::default();
+        sent.expect("r1", "a@s.whatsapp.net", "hi");
+        sent.expect("r2", "a@s.whatsapp.net", "hi");
+        // r1's echo before its ack, r2's ack before its echo.
+        assert!(sent.is_echo("a@s.whatsapp.net", "hi", "M1"));
+        sent.resolved("r1", Some("M1"));
+        sent.resolved("r2", Some("M2"));
+        assert!(sent.is_echo("a@s.whatsapp.net", "hi", "M2"));
+        assert!(sent.contains("M1") && sent.contains("M2"));
+        // Nothing pending any more: the owner typing "hi" is their own message.
+        assert!(!sent.is_echo("a@s.whatsapp.net", "hi", "M3"));
+    }
+
+    #[test]
+    fn sent_ids_match_chat_and_text() {
+        let sent = SentIds::default();
+        sent.expect("r1", "a@s.whatsapp.net", "hi");
+        assert!(!sent.is_echo("b@s.whatsapp.net", "hi", "M1"));
+        assert!(!sent.is_echo("a@s.whatsapp.net", "hello", "M2"));
+        // A failed send leaves nothing to claim.
+        sent.resolved("r1", None);
+        assert!(!sent.is_echo("a@s.whatsapp.net", "hi", "M3"));
+        assert!(!sent.contains("M1"));
+    }
+
+    #[test]
+    fn commands_serialise_as_documented() {
+        let jid = || "a@s.whatsapp.net".to_string();
+        assert_eq!(
+            wire(BridgeCommand::Send { id: "r1".into(), jid: jid(), text: "hi".into(), quoted: None }),
+            json!({ "type": "send", "id": "r1", "jid": "a@s.whatsapp.net", "text": "hi" }),
+        );
+        assert_eq!(
+            wire(BridgeCommand::Send {
+                id: "r2".into(),
+                jid: jid(),
+                text: "hi".into(),
+                quoted: Some(ReplyTo { key: key(Some("b@s.whatsapp.net")), text: "question".into() }),
+            }),
+            json!({ "type": "send", "id": "r2", "jid": "a@s.whatsapp.net", "text": "hi", "quoted": {
+                "messageId": "IN1", "participant": "b@s.whatsapp.net", "fromMe": false, "text": "question",
+            } }),
+        );
+        assert_eq!(
+            wire(BridgeCommand::SendFile {
+                id: "r3".into(),
+                jid: jid(),
+                path: "/srv/report.pdf".into(),
+                kind: AttachmentKind::Document,
+                mime_type: "application/pdf".into(),
+                file_name: Some("report.pdf".into()),
+                caption: Some("here".into()),
+            }),
+            json!({ "type": "send_file", "id": "r3", "jid": "a@s.whatsapp.net", "path": "/srv/report.pdf",
+                "kind": "document", "mimeType": "application/pdf", "fileName": "report.pdf", "caption": "here" }),
+        );
+        assert_eq!(
+            wire(BridgeCommand::Presence { id: "r4".into(), jid: jid(), state: Presence::Composing }),
+            json!({ "type": "presence", "id": "r4", "jid": "a@s.whatsapp.net", "state": "composing" }),
+        );
+        assert_eq!(
+            wire(BridgeCommand::Read { id: "r5".into(), jid: jid(), key: key(None) }),
+            json!({ "type": "read", "id": "r5", "jid": "a@s.whatsapp.net", "messageId": "IN1", "fromMe": false }),
+        );
+        assert_eq!(
+            wire(BridgeCommand::React { id: "r6".into(), jid: jid(), key: key(Some("b@s.whatsapp.net")), emoji: "⚙️".into() }),
+            json!({ "type": "react", "id": "r6", "jid": "a@s.whatsapp.net", "messageId": "IN1",
+                "participant": "b@s.whatsapp.net", "fromMe": false, "emoji": "⚙️" }),
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! One agent and session per WhatsApp chat.
+//!
+//! Every chat gets a worker task that owns its `Agent`, so chats are answered
+//! concurrently while the messages of one chat are handled in arrival order.
+//! Workers are started on the first message and dropped after the idle timeout;
+//! the next message reloads the history from `session_dir/whatsapp/<jid>.jsonl`.
+//! A worker's logs carry its chat's JID and role. Group workers also hold the
+//! group's recent passive messages (see `groups`).
+
+use crate::approvals::{Approvals, RemoteApprover};
+use crate::bridge::{Attachment, BridgeSender, ReplyTo};
+use crate::groups;
+use crate::media;
+use crate::progress::{self, Progress};
+use crate::send_file::SendFile;
+use anyhow::Result;
+use futures::StreamExt;
+use kova_core::agent::Agent;
+use kova_core::approval::{Approval, Approver};
+use kova_core::config::{Config, SenderRole};
+use kova_core::event::EventPayload;
+use kova_core::llm;
+use kova_core::policy::ChannelPolicy;
+use kova_core::session::Session;
+use kova_core::text;
+use kova_core::tools::shell::ShellExec;
+use kova_core::tools::workspace::Workspace;
+use kova_core::tools::ToolCall;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+/// Builds the agent for a chat.
+pub struct AgentFactory {
+    config: Config,
+    identity: String,
+    workspace: Workspace,
+    session_dir: PathBuf,
+}
+
+impl AgentFactory {
+    /// Checks up front that every role's policy channel compiles.
+    pub fn new(config: Config, identity: String, workspace: Workspace, session_dir: PathBuf) -> Result<Self> {
+        let factory = Self { config, identity, workspace, session_dir };
+        for role in [SenderRole::Owner, SenderRole::Trusted, SenderRole::Guest] {
+            factory.policy(role)?;
+            if let Some(channel) = factory.config.whatsapp.role(role).policy {
+                if !factory.config.policy.channels.contains_key(&channel) {
+                    tracing::warn!("role {} uses policy channel {channel}, which is not configured", role.as_str());
+                }
+            }
+        }
+        Ok(factory)
+    }
+
+    fn policy(&self, role: SenderRole) -> Result<ChannelPolicy> {
+        match self.config.whatsapp.role(role).policy {
+            Some(channel) => self.config.policy.channel(&channel),
+            None => Ok(ChannelPolicy::deny_all(role.as_str())),
+        }
+    }
+
+    fn build(&self, jid: &str, role: SenderRole, bridge: &BridgeSender) -> Result<Agent> {
+        let session = Session::new(&self.session_dir, &session_id(jid))?;
+        let llm = llm::from_config(self.config.llm.clone())?;
+        let mut agent = Agent::new(llm, self.identity.clone()).with_session(session)?;
+        agent.tools.set_policy(self.policy(role)?);
+        agent.tools.set_workspace(self.workspace.clone());
+        agent.tools.register(Box::new(ShellExec::new(self.config.shell.clone())));
+        agent.tools.register(Box::new(SendFile::new(bridge.clone(), jid, self.config.whatsapp.max_media_bytes)));
+        agent.tools.set_concurrency(self.config.max_parallel_tools);
+        Ok(agent)
+    }
+}
+
+/// JIDs are safe file names already; anything unexpected is replaced.
+fn session_id(jid: &str) -> String {
+    jid.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "@.-_".contains(c) { c } else { '_' })
+        .collect()
+}
+
+/// A message handed to a chat worker.
+pub struct Incoming {
+    pub text: String,
+    pub label: String,
+    pub attachment: Option<Box<Attachment>>,
+    /// A group message that did not address kova; kept as context for the next answer.
+    pub passive: bool,
+    /// The original message, marked read, reacted to and quoted by the answer.
+    pub reply_to: Option<ReplyTo>,
+}
+
+struct ChatHandle {
+    tx: mpsc::UnboundedSender<Incoming>,
+    /// Messages sent to the worker and not yet answered.
+    pending: Arc<AtomicUsize>,
+    last_active: Instant,
+}
+
+/// Routes messages to per-chat workers.
+pub struct Chats {
+    factory: Arc<AgentFactory>,
+    bridge: BridgeSender,
+    /// Asks the owner about `ask` tool calls; without it they are denied.
+    approvals: Option<Arc<Approvals>>,
+    idle_timeout: Duration,
+    chats: HashMap<String, ChatHandle>,
+}
+
+impl Chats {
+    pub fn new(
+        factory: AgentFactory,
+        bridge: BridgeSender,
+        approvals: Option<Arc<Approvals>>,
+        idle_timeout: Duration,
+    ) -> Self {
+        Self {
+            factory: Arc::new(factory),
+            bridge,
+            approvals,
+            idle_timeout,
+            chats: HashMap::new(),
+        }
+    }
+
+    /// Queues a message for its chat, starting the chat's worker with `role` if needed.
+    pub fn dispatch(&mut self, jid: &str, role: SenderRole, incoming: Incoming) {
+        let incoming = match self.chats.get_mut(jid) {
+            Some(chat) => {
+                chat.pending.fetch_add(1, Ordering::SeqCst);
+                match chat.tx.send(incoming) {
+                    Ok(()) => {
+                        chat.last_active = Instant::now();
+                        return;
+                    }
+                    // The worker is gone (it failed to start); start a new one.
+                    Err(mpsc::error::SendError(incoming)) => incoming,
+                }
+            }
+            None => incoming,
+        };
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(1));
+        let _ = tx.send(incoming);
+        let span = tracing::info_span!("chat", jid, role = role.as_str());
+        tokio::spawn(run_chat(
+            jid.to_string(),
+            role,
+            rx,
+            pending.clone(),
+            self.factory.clone(),
+            self.bridge.clone(),
+            self.approvals.clone(),
+        ).instrument(span));
+        self.chats.insert(jid.to_string(), ChatHandle { tx, pending, last_active: Instant::now() });
+    }
+
+    /// Drops workers that have been idle past the timeout. Their sessions stay on disk.
+    pub fn evict_idle(&mut self) {
+        let timeout = self.idle_timeout;
+        self.chats.retain(|jid, chat| {
+            let idle = chat.pending.load(Ordering::SeqCst) == 0 && chat.last_active.elapsed() >= timeout;
+            if idle {
+                tracing::debug!("unloading idle chat {jid}");
+            }
+            !idle
+        });
+    }
+}
+
+async fn run_chat(
+    jid: String,
+    role: SenderRole,
+    mut rx: mpsc::UnboundedReceiver<Incoming>,
+    pending: Arc<AtomicUsize>,
+    factory: Arc<AgentFactory>,
+    bridge: BridgeSender,
+    approvals: Option<Arc<Approvals>>,
+) {
+    let mut agent = match factory.build(&jid, role, &bridge) {
+        Ok(agent) => agent,
+        Err(e) => {
+            tracing::error!("could not start agent for {jid}: {e}");
+            return;
+        }
+    };
+    tracing::debug!("loaded chat {jid}");
+    let vision = factory.config.llm.vision;
+    let context_limit = factory.config.whatsapp.groups.context_messages;
+    let mut context = VecDeque::new();
+
+    while let Some(mut incoming) = rx.recv().await {
+        if incoming.passive {
+            if context_limit > 0 {
+                context.push_back(match &incoming.attachment {
+                    Some(attachment) => format!("{} [{}]", incoming.text, media::describe(attachment)),
+                    None => incoming.text,
+                });
+                if context.len() > context_limit {
+                    context.pop_front();
+                }
+            }
+        } else {
+            if !context.is_empty() {
+                let lines: Vec<String> = context.drain(..).collect();
+                incoming.text = format!("{}\n{}\n\n{}", groups::CONTEXT_HEADER, lines.join("\n"), incoming.text);
+            }
+            if let Err(e) = respond(&mut agent, &jid, incoming, vision, &bridge, approvals.clone()).await {
+                tracing::error!("reply to {jid} failed: {e}");
+            }
+        }
+        pending.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn respond(
+    agent: &mut Agent,
+    jid: &str,
+    mut incoming: Incoming,
+    vision: bool,
+    bridge: &BridgeSender,
+    approvals: Option<Arc<Approvals>>,
+) -> Result<()> {
+    let approver: Box<dyn Approver> = match approvals {
+        Some(approvals) => {
+            let chat = if incoming.label == jid { jid.to_string() } else { format!("{} ({jid})", incoming.label) };
+            Box::new(RemoteApprover::new(approvals, chat))
+        }
+        // Nobody can answer an approval prompt, so `ask` means no.
+        None => Box::new(|_: &ToolCall, _: bool| Approval::deny("no approval owner is configured for WhatsApp")),
+    };
+
+    let reply_to = incoming.reply_to.as_ref();
+    let mut progress = Progress::start(bridge, jid, reply_to.map(|r| &r.key)).await;
+    let mut typing = tokio::time::interval_at(tokio::time::Instant::now() + progress::TYPING_REFRESH, progress::TYPING_REFRESH);
+
+    let (text, attachment) = (incoming.text.clone(), incoming.attachment.take());
+    let message = tokio::task::spawn_blocking(move || media::user_message(&text, attachment.as_deref(), vision)).await?;
+    let stream = agent.run_stream_message(message, approver.as_ref());
+    let mut stream = std::pin::pin!(stream);
+    let mut final_text = String::new();
+    let mut failure = None;
+
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = typing.tick() => {
+                progress.typing().await;
+                continue;
+            }
+        };
+        match event {
+            Ok(event) => {
+                progress.update(&event.payload).await;
+                match event.payload {
+                    EventPayload::ToolResult { name, output, success, .. } => {
+                        let status = if success { "ok" } else { "fail" };
+                        let preview = text::truncate(&output, 100);
+                        println!("  [tool:{name} -> {status}] {preview}");
+                    }
+                    EventPayload::FinalAnswer { text } => final_text = text,
+                    _ => {}
+                }
+            }
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    progress.finish().await;
+
+    if let Some(e) = failure {
+        tracing::error!("agent error: {e}");
+        bridge.reply_text(jid, &format!("Error: {e}"), reply_to).await?;
+    } else if !final_text.trim().is_empty() {
+        println!("[kova -> {}] {final_text}", incoming.label);
+        bridge.reply_text(jid, &final_text, reply_to).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,187 @@
+//! When kova speaks up in group chats.
+//!
+//! Groups are noisy, so kova answers a group message only when it addresses kova:
+//! the account is @-mentioned, one of kova's messages is replied to, or the text
+//! starts with `whatsapp.groups.prefix`. Other messages of allowed groups are passed
+//! to the chat worker as passive context; it keeps the latest of them and hands
+//! them to the agent together with the next message it answers.
+
+use crate::bridge::{Quoted, SentIds};
+use kova_core::text;
+
+/// Quoted text shown to the agent is cut to this length.
+const QUOTE_CHARS: usize = 200;
+
+/// Introduces the passive messages handed to the agent with an answered one.
+pub const CONTEXT_HEADER: &str = "Recent messages in this group (not addressed to you):";
+
+pub fn is_group(jid: &str) -> bool {
+    jid.ends_with("@g.us")
+}
+
+/// `905551234567:12@s.whatsapp.net` -> `905551234567@s.whatsapp.net`.
+fn bare_jid(jid: &str) -> String {
+    match jid.split_once('@') {
+        Some((user, server)) => {
+            let user = user.split(':').next().unwrap_or(user);
+            format!("{user}@{server}")
+        }
+        None => jid.to_string(),
+    }
+}
+
+/// The phone number or id part of a JID.
+pub fn jid_user(jid: &str) -> &str {
+    jid.split(['@', ':']).next().unwrap_or(jid)
+}
+
+/// The JIDs kova's account is known by, as reported on `connected`.
+#[derive(Default)]
+pub struct Me {
+    jids: Vec<String>,
+}
+
+impl Me {
+    pub fn set(&mut self, jid: Option<&str>, lid: Option<&str>) {
+        self.jids = jid.into_iter().chain(lid).map(bare_jid).collect();
+    }
+
+    pub fn is(&self, jid: &str) -> bool {
+        let jid = bare_jid(jid);
+        self.jids.contains(&jid)
+    }
+}
+
+/// What a group message is for kova.
+pub enum Addressed {
+    /// Answer it; the text has the prefix removed and kova's mentions spelled `@kova`.
+    Yes(String),
+    /// Keep it as context only.
+    No,
+}
+
+/// Decides whether a group message addresses kova.
+pub fn addressed(
+    text: &str,
+    mentions: &[String],
+    quoted: Option<&Quoted>,
+    me: &Me,
+    sent: &SentIds,
+    prefix: &str,
+) -> Addressed {
+    let mentioned = mentions.iter().any(|m| me.is(m));
+    let replied = quoted.is_some_and(|q| sent.contains(&q.message_id));
+    let rest = strip_prefix(text, prefix);
+    if !mentioned && !replied && rest.is_none() {
+        return Addressed::No;
+    }
+
+    let mut text = rest.unwrap_or(text).to_string();
+    for mention in mentions.iter().filter(|m| me.is(m)) {
+        text = text.replace(&format!("@{}", jid_user(mention)), "@kova");
+    }
+    Addressed::Yes(text)
+}
+
+/// `text` after a case-insensitive `prefix`, if it starts with one as a whole word.
+fn strip_prefix<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let text = text.trim_start();
+    let chars = prefix.chars().count();
+    if prefix.is_empty() || text.chars().count() < chars {
+        return None;
+    }
+    let end = text.char_indices().nth(chars).map_or(text.len(), |(i, _)| i);
+    let (head, rest) = text.split_at(end);
+    let whole_word = rest.chars().next().is_none_or(|c| !c.is_alphanumeric());
+    (head.to_lowercase() == prefix.to_lowercase() && whole_word)
+        .then(|| rest.trim_start_matches([',', ':']).trim_start())
+}
+
+/// `[label] text` for the agent, with the message it replies to unless that is kova's own.
+pub fn attributed(label: &str, text: &str, quoted: Option<&Quoted>, sent: &SentIds) -> String {
+    match quoted.filter(|q| !sent.contains(&q.message_id) && !q.text.is_empty()) {
+        Some(q) => {
+            let author = q.participant.as_deref().map(jid_user).unwrap_or("someone");
+            let quote = text::truncate(&q.text, QUOTE_CHARS);
+            format!("[{label}, replying to {author}: \"{quote}\"] {text}")
+        }
+        None => format!("[{label}] {text}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GROUP: &str = "120363041234567890@g.us";
+
+    fn me() -> Me {
+        let mut me = Me::default();
+        me.set(Some("905551234567:14@s.whatsapp.net"), Some("187340291845120:14@lid"));
+        me
+    }
+
+    /// The text kova answers, or `None` for a passive message.
+    fn answered(text: &str, mentions: &[&str], quoted: Option<&str>, sent: &SentIds, prefix: &str) -> Option<String> {
+        let mentions: Vec<String> = mentions.iter().map(|m| m.to_string()).collect();
+        let quoted = quoted.map(|id| Quoted { message_id: id.into(), participant: None, text: "earlier".into() });
+        match addressed(text, &mentions, quoted.as_ref(), &me(), sent, prefix) {
+            Addressed::Yes(text) => Some(text),
+            Addressed::No => None,
+        }
+    }
+
+    #[test]
+    fn mentions_of_kova_are_answered_and_respelled() {
+        let sent = SentIds::default();
+        assert_eq!(
+            answered("@905551234567 what time is it?", &["905551234567@s.whatsapp.net"], None, &sent, "!kova").as_deref(),
+            Some("@kova what time is it?"),
+        );
+        assert_eq!(
+            answered("@187340291845120 hi", &["187340291845120@lid"], None, &sent, "!kova").as_deref(),
+            Some("@kova hi"),
+        );
+        assert_eq!(answered("@905559876543 hi", &["905559876543@s.whatsapp.net"], None, &sent, "!kova"), None);
+    }
+
+    #[test]
+    fn replies_to_kova_are_answered() {
+        let sent = SentIds::default();
+        sent.expect("c1", GROUP, "Done.");
+        sent.resolved("c1", Some("3EB0C431C26A1D3F9A"));
+        assert_eq!(answered("thanks!", &[], Some("3EB0C431C26A1D3F9A"), &sent, "!kova").as_deref(), Some("thanks!"));
+        assert_eq!(answered("thanks!", &[], Some("3EB0FFFFFFFFFFFFFF"), &sent, "!kova"), None);
+    }
+
+    #[test]
+    fn prefix_must_open_the_message_as_a_whole_word() {
+        let sent = SentIds::default();
+        let prefixed = |text: &str| answered(text, &[], None, &sent, "!kova");
+        assert_eq!(prefixed("!kova what's the weather").as_deref(), Some("what's the weather"));
+        assert_eq!(prefixed("  !KOVA, hi").as_deref(), Some("hi"));
+        assert_eq!(prefixed("!kova: hi").as_deref(), Some("hi"));
+        assert_eq!(prefixed("!kova").as_deref(), Some(""));
+        assert_eq!(prefixed("!kovax hi"), None);
+        assert_eq!(prefixed("hey !kova hi"), None);
+        assert_eq!(prefixed("!kov"), None);
+        assert_eq!(answered("!kova hi", &[], None, &sent, ""), None);
+    }
+
+    #[test]
+    fn prefix_matches_by_characters() {
+        assert_eq!(strip_prefix("Çay, demle", "çay"), Some("demle"));
+        assert_eq!(strip_prefix("ÇAY", "çay"), Some(""));
+        assert_eq!(strip_prefix("çaydanlık", "çay"), None);
+        // The prefix's byte length falls inside `€`.
+        assert_eq!(strip_prefix("kov€ hi", "kova"), None);
+        assert_eq!(strip_prefix("😀 bak", "😀"), Some("bak"));
+    }
+
+    #[test]
+    fn other_messages_stay_passive() {
+        let sent = SentIds::default();
+        assert_eq!(answered("anyone up for lunch?", &[], None, &sent, "!kova"), None);
+        assert_eq!(answered("kova is a bot", &[], Some("3EB0AAAAAAAAAAAAAA"), &sent, "!kova"), None);
+    }
+}
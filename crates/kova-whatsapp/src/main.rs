@@ -1,123 +1,216 @@
+mod access;
+mod approvals;
 mod bridge;
+mod chats;
+mod groups;
+mod media;
+mod progress;
+mod send_file;
+mod supervisor;
+#[cfg(test)]
+mod testing;
 
+use access::{Access, AccessControl};
+use approvals::Approvals;
 use anyhow::Result;
-use bridge::{BaileysBridge, BridgeEvent};
-use kova_core::agent::Agent;
-use kova_core::config::Config;
-use kova_core::llm::LlmClient;
-use std::collections::HashSet;
+use bridge::{BridgeEvent, BridgeLaunch, MessageKey, ReplyTo};
+use chats::{AgentFactory, Chats, Incoming};
+use groups::{Addressed, Me};
+use kova_core::config::{self, Config};
 use std::path::PathBuf;
-use std::time::Instant;
+use std::time::Duration;
+use supervisor::BridgeSupervisor;
+use tokio::io::AsyncBufReadExt;
 
-// Tools that auto-approve without user confirmation on WhatsApp
-const WA_AUTO_APPROVE: &[&str] = &["read_file", "shell_exec"];
+/// How often idle chats are unloaded.
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often media past `whatsapp.media_retention_days` is deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let base_dir = find_project_root()?;
-    let config = Config::load(&base_dir.join("config/kovaclaw.json"))?;
+    let base_dir = config::find_project_root()?;
+    let config = Config::load(&base_dir.join(config::CONFIG_FILE))?;
     let identity = config.load_identity(&base_dir)?;
-
-    let llm = LlmClient::new(config.llm);
-    let mut agent = Agent::new(llm, identity);
-
-    let whitelist: HashSet<String> = WA_AUTO_APPROVE.iter().map(|s| s.to_string()).collect();
+    let workspace = config.workspace(&base_dir)?;
+    let session_dir = base_dir.join(&config.session_dir).join("whatsapp");
+    let idle_timeout = Duration::from_secs(config.whatsapp.idle_timeout_secs);
+    let mut access = AccessControl::new(&config.whatsapp);
+    if config.whatsapp.senders.is_empty() {
+        tracing::warn!("whatsapp.senders is empty, every incoming message will be ignored");
+    }
+    let media_dir = base_dir.join(&config.whatsapp.media_dir);
+    std::fs::create_dir_all(&media_dir)?;
+    let media_dir = media_dir.canonicalize()?;
+    let media_retention = Duration::from_secs(config.whatsapp.media_retention_days * 24 * 60 * 60);
+    let media_from = config.whatsapp.senders.keys().cloned().collect();
+    let max_media_bytes = config.whatsapp.max_media_bytes;
+    let group_prefix = config.whatsapp.groups.prefix.clone();
+    let approval_owner = config.whatsapp.approval_owner();
+    if approval_owner.is_none() {
+        tracing::warn!("no WhatsApp owner to ask for tool approval, tool calls the policy marks `ask` will be denied");
+    }
+    let approval_timeout = Duration::from_secs(config.whatsapp.approvals.timeout_secs);
+    let factory = AgentFactory::new(config, identity, workspace, session_dir)?;
 
     let bridge_dir = base_dir.join("bridge");
-    let auth_dir = std::env::var("BAILEYS_AUTH_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| bridge_dir.join("auth_state"));
+    let launch = BridgeLaunch {
+        auth_dir: std::env::var("BAILEYS_AUTH_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| bridge_dir.join("auth_state")),
+        // `fake_bridge.js` replays scripted events for testing without a WhatsApp account.
+        script: std::env::var("KOVA_BRIDGE_SCRIPT").unwrap_or_else(|_| "baileys_bridge.js".into()),
+        bridge_dir,
+        media_dir: media_dir.clone(),
+        max_media_bytes,
+        media_from,
+    };
 
-    let mut last_self_send: Option<Instant> = None;
+    let mut me = Me::default();
 
     println!("[kovaclaw-wa] starting bridge...");
-    println!("[kovaclaw-wa] auto-approve tools: {:?}", WA_AUTO_APPROVE);
-    let (mut bridge, mut events) = BaileysBridge::spawn(&bridge_dir, &auth_dir).await?;
+    let (supervisor, mut events) = BridgeSupervisor::start(launch);
+    let sender = supervisor.sender();
+    let sent_ids = sender.sent_ids();
+    let approvals = approval_owner.map(|owner| Approvals::new(owner, approval_timeout, sender.clone()));
+    let mut chats = Chats::new(factory, sender.clone(), approvals.clone(), idle_timeout);
+    let mut evict = tokio::time::interval(EVICT_INTERVAL);
+    let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+    let mut console = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut console_open = true;
 
-    while let Some(event) = events.recv().await {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => event,
+                // The supervisor gave up on the bridge.
+                None => break,
+            },
+            _ = evict.tick() => {
+                chats.evict_idle();
+                continue;
+            }
+            _ = prune.tick(), if !media_retention.is_zero() => {
+                match media::prune(&media_dir, media_retention) {
+                    Ok(0) => {}
+                    Ok(removed) => tracing::info!("deleted {removed} media files older than {} days", media_retention.as_secs() / 86400),
+                    Err(e) => tracing::warn!("cleaning up {} failed: {e}", media_dir.display()),
+                }
+                continue;
+            }
+            line = console.next_line(), if console_open => {
+                match line {
+                    Ok(Some(line)) if line.trim() == "status" => println!("[kovaclaw-wa] {}", supervisor.report()),
+                    Ok(Some(line)) if line.trim().is_empty() => {}
+                    Ok(Some(line)) => println!("[kovaclaw-wa] unknown command: {} (try `status`)", line.trim()),
+                    Ok(None) | Err(_) => console_open = false,
+                }
+                continue;
+            }
+        };
         match event {
-            BridgeEvent::Connected => {
+            BridgeEvent::Connected { jid, lid } => {
+                if jid.is_none() {
+                    tracing::warn!("bridge did not report the account JID, group mentions will not be recognised");
+                }
+                me.set(jid.as_deref(), lid.as_deref());
                 println!("[kovaclaw-wa] connected to WhatsApp");
             }
+            // `logged_out` stops the supervisor, which ends the event stream.
             BridgeEvent::Disconnected { reason } => {
                 println!("[kovaclaw-wa] disconnected: {reason}");
-                break;
             }
-            BridgeEvent::Message { jid, text, push_name, from_me, .. } => {
-                let label = if push_name.is_empty() { &jid } else { &push_name };
+            BridgeEvent::Message { jid, participant, text, push_name, message_id, from_me, mentions, quoted, attachment } => {
+                tracing::debug!("message {message_id} from {jid}");
+                let author = participant.as_deref().unwrap_or(&jid);
+                let label = if push_name.is_empty() { author.to_string() } else { push_name };
 
-                if from_me {
-                    // Skip kova's own messages (echoed back within 30s of sending)
-                    if let Some(t) = last_self_send {
-                        if t.elapsed().as_secs() < 30 {
-                            println!("[kova echo, skipped]");
-                            continue;
-                        }
+                if from_me && sent_ids.is_echo(&jid, &text, &message_id) {
+                    println!("[kova echo, skipped]");
+                    continue;
+                }
+                let shown = match &attachment {
+                    Some(attachment) => format!("[{}] {text}", media::describe(attachment)),
+                    None => text.clone(),
+                };
+                let group = groups::is_group(&jid);
+                match (from_me, group) {
+                    (true, false) => println!("[self] {shown}"),
+                    (false, false) => println!("[{label}] {shown}"),
+                    (_, true) => println!("[{label} in {jid}] {shown}"),
+                }
+
+                // Approval answers come from the owner's own chat. Counting what this
+                // account typed there only holds when the owner is this account.
+                if let Some(approvals) = approvals.as_ref().filter(|a| a.owner() == jid && (!from_me || me.is(&jid))) {
+                    if approvals.answer(&text).await {
+                        continue;
                     }
-                    println!("[self] {text}");
-                } else {
-                    println!("[{label}] {text}");
                 }
 
-                let wl = whitelist.clone();
-                match agent.run_loop(&text, |name| wl.contains(name)).await {
-                    Ok(result) => {
-                        for exec in &result.tool_log {
-                            let status = if exec.success { "ok" } else { "fail" };
-                            let preview = if exec.output.len() > 100 {
-                                format!("{}...", &exec.output[..100])
-                            } else {
-                                exec.output.clone()
-                            };
-                            println!("  [tool:{} -> {status}] {preview}", exec.name);
+                let reply_to = (!message_id.is_empty()).then(|| ReplyTo {
+                    key: MessageKey { message_id: message_id.clone(), participant: participant.clone(), from_me },
+                    text: text.clone(),
+                });
+                let text = if group {
+                    let attributed = |text: &str| groups::attributed(&label, text, quoted.as_ref(), &sent_ids);
+                    match groups::addressed(&text, &mentions, quoted.as_ref(), &me, &sent_ids, &group_prefix) {
+                        Addressed::Yes(text) => attributed(&text),
+                        Addressed::No => {
+                            if let Some(role) = access.role(&jid) {
+                                let text = attributed(&text);
+                                chats.dispatch(&jid, role, Incoming { text, label, attachment, passive: true, reply_to: None });
+                            }
+                            continue;
                         }
+                    }
+                } else {
+                    text
+                };
 
-                        if !result.final_text.trim().is_empty() {
-                            let text = if result.final_text.len() > 4000 {
-                                format!("{}...\n[truncated]", &result.final_text[..4000])
-                            } else {
-                                result.final_text
-                            };
-                            println!("[kova -> {label}] {text}");
-                            last_self_send = Some(Instant::now());
-                            bridge.send_message(&jid, &text).await?;
+                match access.check(&jid) {
+                    Access::Allowed(role) => {
+                        chats.dispatch(&jid, role, Incoming { text, label, attachment, passive: false, reply_to });
+                    }
+                    Access::Unknown => {
+                        tracing::warn!(jid, "unknown sender, message ignored");
+                        // Never answer what the owner typed into someone else's chat.
+                        if let Some(reply) = access.unknown_reply(&jid).filter(|_| !from_me) {
+                            sender.send_text(&jid, reply).await?;
                         }
                     }
-                    Err(e) => {
-                        tracing::error!("agent error: {e}");
-                        last_self_send = Some(Instant::now());
-                        bridge.send_message(&jid, &format!("Error: {e}")).await?;
+                    Access::RateLimited(role) => {
+                        tracing::warn!(jid, role = role.as_str(), "rate limit reached, message dropped");
                     }
                 }
             }
-            BridgeEvent::Sent { jid } => {
-                tracing::debug!("sent to {jid}");
+            BridgeEvent::Ack { id, message_id } => {
+                tracing::debug!("bridge acked {id}");
+                sender.resolve(&id, Ok(message_id));
             }
-            BridgeEvent::Qr { .. } => {
+            BridgeEvent::Qr { data } => {
+                tracing::debug!("qr payload: {data}");
                 println!("[kovaclaw-wa] QR code generated (check terminal)");
             }
-            BridgeEvent::Error { message } => {
+            BridgeEvent::Error { id: Some(id), message, .. } => {
+                tracing::error!("bridge command {id} failed: {message}");
+                sender.resolve(&id, Err(message));
+            }
+            BridgeEvent::Error { id: None, message, .. } => {
                 tracing::error!("bridge error: {message}");
             }
+            BridgeEvent::Hello { protocol, .. } => {
+                tracing::warn!("unexpected hello (protocol v{protocol}) after the handshake");
+            }
         }
     }
 
-    bridge.kill().await?;
-    Ok(())
-}
-
-fn find_project_root() -> Result<PathBuf> {
-    if let Ok(root) = std::env::var("KOVACLAW_ROOT") {
-        return Ok(PathBuf::from(root));
+    if let Some(error) = supervisor.status().last_error {
+        println!("[kovaclaw-wa] bridge stopped: {error}");
     }
-    let mut dir = std::env::current_dir()?;
-    loop {
-        if dir.join("config/kovaclaw.json").exists() {
-            return Ok(dir);
-        }
-        if !dir.pop() { break; }
-    }
-    anyhow::bail!("kovaclaw.json not found. Set KOVACLAW_ROOT or run from the kovaclaw/ directory.")
+    supervisor.shutdown();
+    Ok(())
 }
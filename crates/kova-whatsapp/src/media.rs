@@ -0,0 +1,187 @@
+//! Turns incoming media into what the agent sees.
+//!
+//! The bridge saves attachments of known chats under `whatsapp.media_dir`. The text of
+//! a document goes into the message itself, and images are attached to it when the
+//! model supports vision, so the agent never needs to open the directory, which holds
+//! every chat's files. Captions stay as text.
+
+use crate::bridge::{Attachment, AttachmentKind, OutgoingFile};
+use kova_core::event::{Image, Message};
+use kova_core::text;
+use kova_core::tools::fs::read_text;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Image types vision models accept.
+const VISION_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// Document text beyond this is cut off.
+const MAX_DOCUMENT_CHARS: usize = 20_000;
+
+/// The user message for `text` and its attachment, if any. Reads documents, so call it
+/// off the async runtime.
+pub fn user_message(text: &str, attachment: Option<&Attachment>, vision: bool) -> Message {
+    let Some(attachment) = attachment else {
+        return Message::user_with_images(text, Vec::new());
+    };
+    let mut images = Vec::new();
+    let note = match &attachment.path {
+        None => format!(
+            "[{} not downloaded: {}]",
+            describe(attachment),
+            attachment.error.as_deref().unwrap_or("unknown error"),
+        ),
+        Some(path) => match attachment.kind {
+            AttachmentKind::Image if vision && VISION_TYPES.contains(&attachment.mime_type.as_str()) => {
+                images.push(Image { path: path.clone(), mime_type: attachment.mime_type.clone() });
+                format!("[{} attached]", describe(attachment))
+            }
+            AttachmentKind::Image => format!("[{}; not shown to the model]", describe(attachment)),
+            AttachmentKind::Document => match read_text(path) {
+                Ok(content) => format!(
+                    "[{}, its text follows]\n{}\n[end of document]",
+                    describe(attachment),
+                    text::truncate(content.trim(), MAX_DOCUMENT_CHARS),
+                ),
+                Err(e) => format!("[{}; its text could not be read: {e}]", describe(attachment)),
+            },
+            AttachmentKind::Audio => format!("[{}; no transcript available]", describe(attachment)),
+            AttachmentKind::Other => format!("[{}]", describe(attachment)),
+        },
+    };
+    let text = text.trim();
+    let content = if text.is_empty() { note } else { format!("{note}\n{text}") };
+    Message::user_with_images(content, images)
+}
+
+/// Short label for logs and the agent, e.g. `document "report.pdf" (application/pdf, 1.2 MB)`.
+pub fn describe(attachment: &Attachment) -> String {
+    let kind = match attachment.kind {
+        AttachmentKind::Image => "image",
+        AttachmentKind::Document => "document",
+        AttachmentKind::Audio => "voice note",
+        AttachmentKind::Other => "file",
+    };
+    let mut details = vec![attachment.mime_type.clone()];
+    if let Some(seconds) = attachment.seconds {
+        details.insert(0, format!("{seconds}s"));
+    }
+    if let Some(size) = attachment.size {
+        details.push(human_size(size));
+    }
+    let details = details.into_iter().filter(|d| !d.is_empty()).collect::<Vec<_>>().join(", ");
+    match &attachment.file_name {
+        Some(name) => format!("{kind} \"{name}\" ({details})"),
+        None => format!("{kind} ({details})"),
+    }
+}
+
+/// Describes a file for sending. Images and audio go out as such, anything else as a
+/// document under its own name.
+pub fn outgoing_file(path: PathBuf) -> OutgoingFile {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let (kind, mime_type) = match extension.as_str() {
+        "jpg" | "jpeg" => (AttachmentKind::Image, "image/jpeg"),
+        "png" => (AttachmentKind::Image, "image/png"),
+        "webp" => (AttachmentKind::Image, "image/webp"),
+        "ogg" | "opus" => (AttachmentKind::Audio, "audio/ogg; codecs=opus"),
+        "mp3" => (AttachmentKind::Audio, "audio/mpeg"),
+        "m4a" => (AttachmentKind::Audio, "audio/mp4"),
+        "pdf" => (AttachmentKind::Document, "application/pdf"),
+        "txt" | "log" | "md" => (AttachmentKind::Document, "text/plain"),
+        "csv" => (AttachmentKind::Document, "text/csv"),
+        "json" => (AttachmentKind::Document, "application/json"),
+        "html" => (AttachmentKind::Document, "text/html"),
+        "zip" => (AttachmentKind::Document, "application/zip"),
+        _ => (AttachmentKind::Document, "application/octet-stream"),
+    };
+    let file_name = (kind == AttachmentKind::Document)
+        .then(|| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .flatten();
+    OutgoingFile { path, kind, mime_type: mime_type.into(), file_name }
+}
+
+/// Deletes files in `dir` last modified more than `max_age` ago. Returns how many.
+pub fn prune(dir: &Path, max_age: Duration) -> std::io::Result<usize> {
+    let Some(cutoff) = SystemTime::now().checked_sub(max_age) else {
+        return Ok(0);
+    };
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && metadata.modified()? < cutoff {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn human_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{} KB", b / 1024),
+        b => format!("{b} bytes"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(kind: AttachmentKind, mime_type: &str, path: Option<PathBuf>) -> Attachment {
+        serde_json::from_value(serde_json::json!({
+            "kind": kind,
+            "mimeType": mime_type,
+            "fileName": "notes.txt",
+            "path": path,
+        })).unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kova-media-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn documents_arrive_inline_without_their_path() {
+        let dir = temp_dir();
+        let path = dir.join("3EB0AA.txt");
+        std::fs::write(&path, "Toplantı saat 10'da 🚀\n").unwrap();
+        let message = user_message("bak", Some(&attachment(AttachmentKind::Document, "text/plain", Some(path.clone()))), false);
+        assert!(message.content.contains("its text follows]\nToplantı saat 10'da 🚀\n[end of document]\nbak"), "{}", message.content);
+        assert!(!message.content.contains(&*dir.to_string_lossy()));
+
+        std::fs::write(&path, [0xff, 0xfe, 0x00]).unwrap();
+        let message = user_message("", Some(&attachment(AttachmentKind::Document, "text/plain", Some(path))), false);
+        assert!(message.content.contains("its text could not be read"), "{}", message.content);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn images_are_attached_for_vision_models_only() {
+        let path = PathBuf::from("/srv/kovaclaw/media/3EB0AA.jpg");
+        let image = attachment(AttachmentKind::Image, "image/jpeg", Some(path.clone()));
+        let message = user_message("", Some(&image), true);
+        assert_eq!(message.images.len(), 1);
+        assert_eq!(message.images[0].path, path);
+        let message = user_message("", Some(&image), false);
+        assert!(message.images.is_empty());
+        assert!(message.content.contains("not shown to the model"));
+    }
+
+    #[test]
+    fn prune_deletes_old_files_only() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("old.jpg"), "").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        std::fs::write(dir.join("new.jpg"), "").unwrap();
+        let age = SystemTime::now().duration_since(std::fs::metadata(dir.join("old.jpg")).unwrap().modified().unwrap()).unwrap();
+        assert_eq!(prune(&dir, age - Duration::from_millis(25)).unwrap(), 1);
+        assert!(!dir.join("old.jpg").exists());
+        assert!(dir.join("new.jpg").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Shows the sender that kova is working on their message.
+//!
+//! A run with several tool rounds can take minutes. While it lasts the message is
+//! marked read, the chat shows "typing..." and, while tools run, the message carries
+//! a reaction. All of it is cosmetic: failures are logged and otherwise ignored.
+
+use crate::bridge::{BridgeSender, MessageKey, Presence};
+use kova_core::event::EventPayload;
+use std::time::Duration;
+
+/// WhatsApp hides "typing..." after about 25s, so it is repeated this often.
+pub const TYPING_REFRESH: Duration = Duration::from_secs(10);
+
+/// Reaction shown on the message while a tool runs.
+const TOOL_REACTION: &str = "⚙️";
+
+pub struct Progress<'a> {
+    bridge: &'a BridgeSender,
+    jid: &'a str,
+    /// The message being answered; `None` if the bridge sent no id.
+    key: Option<&'a MessageKey>,
+    /// Tools requested and not yet finished.
+    running: usize,
+}
+
+impl<'a> Progress<'a> {
+    /// Marks the message read and starts "typing...".
+    pub async fn start(bridge: &'a BridgeSender, jid: &'a str, key: Option<&'a MessageKey>) -> Self {
+        let progress = Self { bridge, jid, key, running: 0 };
+        if let Some(key) = key {
+            progress.log(bridge.mark_read(jid, key).await);
+        }
+        progress.typing().await;
+        progress
+    }
+
+    pub async fn typing(&self) {
+        self.log(self.bridge.presence(self.jid, Presence::Composing).await);
+    }
+
+    /// Follows the agent's events: the reaction is set when the first tool starts
+    /// and removed when the last one has finished.
+    pub async fn update(&mut self, payload: &EventPayload) {
+        match payload {
+            EventPayload::ToolRequest { .. } => {
+                self.running += 1;
+                if self.running == 1 {
+                    self.react(TOOL_REACTION).await;
+                }
+            }
+            EventPayload::ToolResult { .. } if self.running > 0 => {
+                self.running -= 1;
+                if self.running == 0 {
+                    self.react("").await;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Stops "typing..." and removes a reaction left by a failed run.
+    pub async fn finish(self) {
+        if self.running > 0 {
+            self.react("").await;
+        }
+        self.log(self.bridge.presence(self.jid, Presence::Paused).await);
+    }
+
+    async fn react(&self, emoji: &str) {
+        if let Some(key) = self.key {
+            self.log(self.bridge.react(self.jid, key, emoji).await);
+        }
+    }
+
+    fn log(&self, result: anyhow::Result<()>) {
+        if let Err(e) = result {
+            tracing::debug!("progress update for {} failed: {e}", self.jid);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! `send_file`: lets the agent send a workspace file into the chat it is answering.
+//! Registered only for WhatsApp chats; paths go through the same workspace checks
+//! and policy `path` rules as `read_file`.
+
+use crate::bridge::{AttachmentKind, BridgeSender};
+use crate::media;
+use anyhow::Result;
+use async_trait::async_trait;
+use kova_core::tools::{Tool, ToolContext, ToolDef, ToolOutput};
+use serde_json::json;
+
+pub struct SendFile {
+    bridge: BridgeSender,
+    jid: String,
+    max_bytes: u64,
+}
+
+impl SendFile {
+    pub fn new(bridge: BridgeSender, jid: &str, max_bytes: u64) -> Self {
+        Self { bridge, jid: jid.into(), max_bytes }
+    }
+}
+
+#[async_trait]
+impl Tool for SendFile {
+    fn definition(&self) -> ToolDef {
+        ToolDef {
+            name: "send_file".into(),
+            description: "Send a file to the user in this WhatsApp chat. Images and audio are sent as such, \
+                anything else as a document. Write the file first, e.g. with write_file."
+                .into(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string", "description": "File path to send, absolute or relative to the working directory" },
+                    "caption": { "type": "string", "description": "Optional text shown with the file" }
+                },
+                "required": ["path"]
+            }),
+        }
+    }
+
+    async fn execute(&self, args: serde_json::Value, ctx: &ToolContext) -> Result<ToolOutput> {
+        let path = args["path"].as_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing 'path' argument"))?;
+        let caption = args["caption"].as_str().filter(|c| !c.trim().is_empty());
+        let path = match ctx.workspace.resolve(path) {
+            Ok(p) => p,
+            Err(e) => return Ok(ToolOutput::error(e)),
+        };
+        let size = match std::fs::metadata(&path) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            Ok(_) => return Ok(ToolOutput::error(format!("{} is not a file", path.display()))),
+            Err(e) => return Ok(ToolOutput::error(e)),
+        };
+        if size > self.max_bytes {
+            return Ok(ToolOutput::error(format!(
+                "{} is {size} bytes, over the {} byte limit",
+                path.display(),
+                self.max_bytes
+            )));
+        }
+
+        let file = media::outgoing_file(path.clone());
+        let kind = match file.kind {
+            AttachmentKind::Image => "an image",
+            AttachmentKind::Audio => "audio",
+            AttachmentKind::Document | AttachmentKind::Other => "a document",
+        };
+        match self.bridge.send_file(&self.jid, file, caption).await {
+            Ok(()) => Ok(ToolOutput { success: true, output: format!("Sent {} as {kind}", path.display()) }),
+            Err(e) => Ok(ToolOutput::error(e)),
+        }
+    }
+}
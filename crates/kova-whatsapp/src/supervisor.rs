@@ -0,0 +1,367 @@
+//! Keeps the bridge process running.
+//!
+//! The supervisor owns the node process and restarts it with exponential backoff
+//! when it dies. Outgoing commands wait in the `BridgeSender` queue until the
+//! bridge is up and connected to WhatsApp. Commands the bridge did not answer
+//! before it died, or refused with `retry` while it reconnected, are sent again;
+//! a message may then arrive twice, but none is lost. A `logged_out` disconnect or
+//! a protocol mismatch is fatal: restarting would not help, so the supervisor stops
+//! and the event stream ends.
+
+use crate::bridge::{BridgeEvent, BridgeLaunch, BridgeProcess, BridgeSender, QueuedCommand, PROTOCOL_VERSION};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A bridge that stayed up this long resets the backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Outgoing commands held while the bridge is down.
+const QUEUE_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeHealth {
+    Starting,
+    Up,
+    /// Crashed; a restart is scheduled.
+    Down,
+    /// Stopped for good (logged out, or a protocol mismatch).
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct BridgeStatus {
+    pub health: BridgeHealth,
+    /// When `health` last changed.
+    pub since: Instant,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// How a bridge process ended.
+enum Exit {
+    /// It died or never came up; try again.
+    Crashed(String),
+    /// Restarting cannot help.
+    Fatal(String),
+    /// kova-whatsapp is shutting down.
+    Closed,
+}
+
+pub struct BridgeSupervisor {
+    task: JoinHandle<()>,
+    sender: BridgeSender,
+    status: watch::Receiver<BridgeStatus>,
+}
+
+impl BridgeSupervisor {
+    /// Starts supervising. Events of every bridge incarnation arrive on the returned channel.
+    pub fn start(launch: BridgeLaunch) -> (Self, mpsc::Receiver<BridgeEvent>) {
+        let (command_tx, command_rx) = mpsc::channel(QUEUE_CAPACITY);
+        let (event_tx, event_rx) = mpsc::channel(100);
+        let (status_tx, status_rx) = watch::channel(BridgeStatus {
+            health: BridgeHealth::Starting,
+            since: Instant::now(),
+            restarts: 0,
+            last_error: None,
+        });
+        let task = tokio::spawn(supervise(launch, command_rx, event_tx, status_tx));
+        let supervisor = Self {
+            task,
+            sender: BridgeSender::new(command_tx),
+            status: status_rx,
+        };
+        (supervisor, event_rx)
+    }
+
+    pub fn sender(&self) -> BridgeSender {
+        self.sender.clone()
+    }
+
+    pub fn status(&self) -> BridgeStatus {
+        self.status.borrow().clone()
+    }
+
+    /// One-line health report for the `status` command.
+    pub fn report(&self) -> String {
+        let status = self.status();
+        let mut report = format!(
+            "bridge {:?} for {}s, {} restarts, {} commands queued",
+            status.health,
+            status.since.elapsed().as_secs(),
+            status.restarts,
+            self.sender.queued(),
+        );
+        if let Some(error) = &status.last_error {
+            report.push_str(&format!(", last error: {error}"));
+        }
+        report
+    }
+
+    /// Stops supervising; the bridge process is killed with it.
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+}
+
+async fn supervise(
+    launch: BridgeLaunch,
+    mut commands: mpsc::Receiver<QueuedCommand>,
+    events: mpsc::Sender<BridgeEvent>,
+    status: watch::Sender<BridgeStatus>,
+) {
+    let set = |health: BridgeHealth, error: Option<String>| {
+        status.send_modify(|s| {
+            if health == BridgeHealth::Down {
+                s.restarts += 1;
+            }
+            s.health = health;
+            s.since = Instant::now();
+            if error.is_some() {
+                s.last_error = error;
+            }
+        });
+    };
+    let mut backoff = MIN_BACKOFF;
+    let mut outbox = Outbox::default();
+
+    loop {
+        set(BridgeHealth::Starting, None);
+        let started = Instant::now();
+        let exit = match BridgeProcess::start(&launch).await {
+            Ok((_, protocol)) if protocol != PROTOCOL_VERSION => Exit::Fatal(format!(
+                "bridge speaks protocol v{protocol}, kova-whatsapp needs v{PROTOCOL_VERSION}"
+            )),
+            Ok((process, _)) => {
+                tracing::info!("bridge up");
+                set(BridgeHealth::Up, None);
+                run(process, &mut commands, &events, &mut outbox).await
+            }
+            Err(e) => Exit::Crashed(format!("bridge failed to start: {e}")),
+        };
+
+        match exit {
+            Exit::Closed => return,
+            Exit::Fatal(reason) => {
+                tracing::error!("bridge stopped for good: {reason}");
+                set(BridgeHealth::Failed, Some(reason));
+                return;
+            }
+            Exit::Crashed(reason) => {
+                outbox.requeue_all();
+                if started.elapsed() >= STABLE_AFTER {
+                    backoff = MIN_BACKOFF;
+                }
+                tracing::warn!("bridge down ({reason}), restarting in {}s", backoff.as_secs());
+                set(BridgeHealth::Down, Some(reason));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Commands taken off the queue that the bridge has not carried out yet.
+#[derive(Default)]
+struct Outbox {
+    /// To write once the bridge is connected, oldest first.
+    held: VecDeque<QueuedCommand>,
+    /// Written and not answered yet, oldest first.
+    in_flight: VecDeque<QueuedCommand>,
+}
+
+impl Outbox {
+    /// Command `id` was answered; nothing more to do for it.
+    fn answered(&mut self, id: &str) {
+        self.in_flight.retain(|c| c.id != id);
+    }
+
+    /// Holds command `id` again. False if it was not in flight. The bridge answers in
+    /// order, so everything held is older than it and the rest in flight is newer.
+    fn retry(&mut self, id: &str) -> bool {
+        let Some(i) = self.in_flight.iter().position(|c| c.id == id) else {
+            return false;
+        };
+        self.held.extend(self.in_flight.remove(i));
+        true
+    }
+
+    /// The bridge died: everything it did not answer goes out again.
+    fn requeue_all(&mut self) {
+        self.held.extend(self.in_flight.drain(..));
+    }
+}
+
+/// Pumps commands into the process and events out of it until it ends. Commands
+/// are only written while the bridge is connected to WhatsApp.
+async fn run(
+    mut process: BridgeProcess,
+    commands: &mut mpsc::Receiver<QueuedCommand>,
+    events: &mpsc::Sender<BridgeEvent>,
+    outbox: &mut Outbox,
+) -> Exit {
+    let mut connected = false;
+    loop {
+        if connected {
+            while let Some(command) = outbox.held.pop_front() {
+                let written = process.write(&command.line).await;
+                outbox.in_flight.push_back(command);
+                if written.is_err() {
+                    return Exit::Crashed(process.exit().await);
+                }
+            }
+        }
+
+        tokio::select! {
+            event = process.next_event() => {
+                let Some(event) = event else {
+                    return Exit::Crashed(process.exit().await);
+                };
+                match &event {
+                    BridgeEvent::Connected { .. } => connected = true,
+                    BridgeEvent::Ack { id, .. } => outbox.answered(id),
+                    BridgeEvent::Error { id: Some(id), retry: true, message } if outbox.retry(id) => {
+                        tracing::debug!("bridge not connected ({message}), holding {id}");
+                        connected = false;
+                        continue;
+                    }
+                    BridgeEvent::Error { id: Some(id), .. } => outbox.answered(id),
+                    _ => {}
+                }
+                let logged_out = matches!(&event, BridgeEvent::Disconnected { reason } if reason == "logged_out");
+                if events.send(event).await.is_err() {
+                    return Exit::Closed;
+                }
+                if logged_out {
+                    return Exit::Fatal("logged out of WhatsApp".into());
+                }
+            }
+            command = commands.recv(), if connected => {
+                let Some(command) = command else {
+                    return Exit::Closed;
+                };
+                outbox.held.push_back(command);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{next_event, FakeBridge};
+
+    /// Waits for the echo of `text`, failing on any command error on the way.
+    async fn expect_echo(events: &mut mpsc::Receiver<BridgeEvent>, text: &str) {
+        loop {
+            match next_event(events).await {
+                BridgeEvent::Message { text: echoed, from_me: true, .. } if echoed == text => return,
+                BridgeEvent::Error { id, message, .. } => panic!("command {id:?} failed: {message}"),
+                _ => {}
+            }
+        }
+    }
+
+    async fn expect_connected(events: &mut mpsc::Receiver<BridgeEvent>) {
+        while !matches!(next_event(events).await, BridgeEvent::Connected { .. }) {}
+    }
+
+    #[tokio::test]
+    async fn holds_commands_until_connected() {
+        let bridge = FakeBridge::new(&[("FAKE_BRIDGE_CONNECT_MS", "500")]);
+        let (supervisor, mut events) = BridgeSupervisor::start(bridge.launch());
+        supervisor.sender().send_text("a@s.whatsapp.net", "early").await.unwrap();
+        expect_echo(&mut events, "early").await;
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn sends_commands_queued_during_a_crash() {
+        let bridge = FakeBridge::new(&[]);
+        let (supervisor, mut events) = BridgeSupervisor::start(bridge.launch());
+        expect_connected(&mut events).await;
+        bridge.signal("USR2");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(supervisor.status().health, BridgeHealth::Down);
+        supervisor.sender().send_text("a@s.whatsapp.net", "while down").await.unwrap();
+        expect_echo(&mut events, "while down").await;
+        assert_eq!(supervisor.status().restarts, 1);
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn resends_commands_unanswered_at_a_crash() {
+        let bridge = FakeBridge::new(&[("FAKE_BRIDGE_SEND_MS", "1500")]);
+        let (supervisor, mut events) = BridgeSupervisor::start(bridge.launch());
+        expect_connected(&mut events).await;
+        supervisor.sender().send_text("a@s.whatsapp.net", "in flight").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        bridge.signal("USR2");
+        expect_echo(&mut events, "in flight").await;
+        assert_eq!(supervisor.status().restarts, 1);
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn resends_commands_refused_while_reconnecting() {
+        let bridge = FakeBridge::new(&[("FAKE_BRIDGE_CONNECT_MS", "500")]);
+        let (supervisor, mut events) = BridgeSupervisor::start(bridge.launch());
+        expect_connected(&mut events).await;
+        bridge.signal("USR1");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let sender = supervisor.sender();
+        sender.send_text("a@s.whatsapp.net", "first").await.unwrap();
+        sender.send_text("a@s.whatsapp.net", "second").await.unwrap();
+        expect_echo(&mut events, "first").await;
+        expect_echo(&mut events, "second").await;
+        assert_eq!(supervisor.status().restarts, 0);
+        supervisor.shutdown();
+    }
+
+    /// Handles the events of one send the way `main` does, with the bridge delivering
+    /// the echo before (`"1"`) or after (`"0"`) the ack.
+    async fn recognises_echo(echo_first: &str) {
+        const JID: &str = "a@s.whatsapp.net";
+        let bridge = FakeBridge::new(&[("FAKE_BRIDGE_ECHO_FIRST", echo_first)]);
+        let (supervisor, mut events) = BridgeSupervisor::start(bridge.launch());
+        let sender = supervisor.sender();
+        let sent = sender.sent_ids();
+        expect_connected(&mut events).await;
+        sender.send_text(JID, "same words").await.unwrap();
+
+        let (mut echo, mut acked) = (None, false);
+        while echo.is_none() || !acked {
+            match next_event(&mut events).await {
+                BridgeEvent::Message { jid, text, message_id, from_me: true, .. } => {
+                    assert_eq!(acked, echo_first == "0");
+                    assert!(sent.is_echo(&jid, &text, &message_id), "echo of {message_id} not recognised");
+                    echo = Some(message_id);
+                }
+                BridgeEvent::Ack { id, message_id } => {
+                    sender.resolve(&id, Ok(message_id));
+                    acked = true;
+                }
+                BridgeEvent::Error { id, message, .. } => panic!("command {id:?} failed: {message}"),
+                _ => {}
+            }
+        }
+        assert!(sent.contains(&echo.unwrap()));
+        // The owner typing the same words afterwards is not an echo.
+        assert!(!sent.is_echo(JID, "same words", "3EB0FFFFFFFFFFFFFFFF"));
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn recognises_echo_after_ack() {
+        recognises_echo("0").await;
+    }
+
+    #[tokio::test]
+    async fn recognises_echo_before_ack() {
+        recognises_echo("1").await;
+    }
+}
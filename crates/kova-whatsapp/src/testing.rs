@@ -0,0 +1,66 @@
+//! `bridge/fake_bridge.js` for the tests, each run in a directory of its own with
+//! its own settings, so tests can signal their bridge without touching others.
+
+use crate::bridge::{BridgeEvent, BridgeLaunch};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const FAKE_BRIDGE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../bridge/fake_bridge.js");
+
+pub struct FakeBridge {
+    dir: PathBuf,
+}
+
+impl FakeBridge {
+    /// A fake bridge started with the `FAKE_BRIDGE_*` settings in `env`.
+    pub fn new(env: &[(&str, &str)]) -> Self {
+        let dir = std::env::temp_dir().join(format!("kova-bridge-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let env: serde_json::Map<String, serde_json::Value> =
+            env.iter().map(|(k, v)| (k.to_string(), (*v).into())).collect();
+        // Every start records its pid for `signal`.
+        let wrapper = format!(
+            "import {{ writeFileSync }} from 'fs';\n\
+             import {{ pathToFileURL }} from 'url';\n\
+             writeFileSync('pid', String(process.pid));\n\
+             Object.assign(process.env, {});\n\
+             await import(pathToFileURL({}).href);\n",
+            serde_json::Value::Object(env),
+            serde_json::Value::from(FAKE_BRIDGE),
+        );
+        std::fs::write(dir.join("fake.mjs"), wrapper).unwrap();
+        Self { dir }
+    }
+
+    pub fn launch(&self) -> BridgeLaunch {
+        BridgeLaunch {
+            bridge_dir: self.dir.clone(),
+            script: "fake.mjs".into(),
+            auth_dir: self.dir.join("auth_state"),
+            media_dir: self.dir.join("media"),
+            max_media_bytes: 1024 * 1024,
+            media_from: Vec::new(),
+        }
+    }
+
+    /// Sends `signal` (`USR1`, `USR2`) to the running bridge process.
+    pub fn signal(&self, signal: &str) {
+        let pid = std::fs::read_to_string(self.dir.join("pid")).unwrap();
+        let status = std::process::Command::new("kill").arg(format!("-{signal}")).arg(pid.trim()).status().unwrap();
+        assert!(status.success(), "kill -{signal} {pid}");
+    }
+}
+
+impl Drop for FakeBridge {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The next event, failing the test after 10s.
+pub async fn next_event(events: &mut mpsc::Receiver<BridgeEvent>) -> BridgeEvent {
+    tokio::time::timeout(Duration::from_secs(10), events.recv()).await
+        .expect("no bridge event within 10s")
+        .expect("bridge event stream ended")
+}